@@ -1,6 +1,28 @@
 use semver::Version;
 use std::{env, error::Error, fs, path::Path, path::PathBuf, process::Command};
 
+/// Collects the entries the build ID is computed from (`build-id` feature); unlike `.defmt` this
+/// section is loaded on the target
+const BUILD_ID_SECTION: &str = "
+SECTIONS
+{
+  .defmt_build_id : ALIGN(4)
+  {
+    __defmt_build_id_start = .;
+    KEEP(*(.defmt_build_id .defmt_build_id.*));
+    __defmt_build_id_end = .;
+  }
+} INSERT AFTER .rodata;
+";
+
+/// Default implementations of the `sinks!` functions (`sinks` feature)
+const SINKS_SYMBOLS: &str = "
+EXTERN(__defmt_default_sinks_write);
+EXTERN(__defmt_default_sinks_end_frame);
+PROVIDE(_defmt_sinks_write = __defmt_default_sinks_write);
+PROVIDE(_defmt_sinks_end_frame = __defmt_default_sinks_end_frame);
+";
+
 fn main() -> Result<(), Box<dyn Error>> {
     // Put the linker script somewhere the linker can find it
     let out = &PathBuf::from(env::var("OUT_DIR")?);
@@ -35,6 +57,30 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     };
     linker_script = linker_script.replace("$DEFMT_VERSION", version.trim());
+    let encoding = if env::var_os("CARGO_FEATURE_ENCODING_RZCOBS").is_some() {
+        "rzcobs"
+    } else {
+        "raw"
+    };
+    linker_script = linker_script.replace("$DEFMT_ENCODING", encoding);
+    let crc = if env::var_os("CARGO_FEATURE_CRC").is_some() {
+        "crc16"
+    } else {
+        "none"
+    };
+    linker_script = linker_script.replace("$DEFMT_CRC", crc);
+    let sequence_numbers = if env::var_os("CARGO_FEATURE_SEQUENCE_NUMBERS").is_some() {
+        "u8"
+    } else {
+        "none"
+    };
+    linker_script = linker_script.replace("$DEFMT_SEQUENCE_NUMBERS", sequence_numbers);
+    if env::var_os("CARGO_FEATURE_BUILD_ID").is_some() {
+        linker_script.push_str(BUILD_ID_SECTION);
+    }
+    if env::var_os("CARGO_FEATURE_SINKS").is_some() {
+        linker_script.push_str(SINKS_SYMBOLS);
+    }
     fs::write(out.join("defmt.x"), linker_script)?;
     println!("cargo:rustc-link-search={}", out.display());
     let target = env::var("TARGET")?;
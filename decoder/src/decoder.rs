@@ -4,7 +4,7 @@ use std::{
     ops::Range,
 };
 
-use crate::{Arg, DecodeError, FormatSliceElement, Table};
+use crate::{Arg, DecodeError, FormatSliceElement, MalformedFrame, MalformedReason, Table};
 use byteorder::{ReadBytesExt, LE};
 use defmt_parser::{get_max_bitfield_range, Fragment, Parameter, Type};
 
@@ -12,10 +12,10 @@ use defmt_parser::{get_max_bitfield_range, Fragment, Parameter, Type};
 #[derive(Debug)]
 enum FormatList<'t> {
     /// Build the list; used when decoding the first element
-    Build { formats: Vec<&'t str> },
+    Build { formats: Vec<(usize, &'t str)> },
     /// Use the list; used when decoding the rest of elements
     Use {
-        formats: Vec<&'t str>,
+        formats: Vec<(usize, &'t str)>,
         cursor: usize,
     },
 }
@@ -26,6 +26,12 @@ pub(crate) struct Decoder<'t, 'b> {
     format_list: Option<FormatList<'t>>,
     // below an enum tags must be included
     below_enum: bool,
+    /// Length prefixes (of strings, slices, etc.) above this value are rejected as malformed
+    pub max_len: usize,
+    /// Length of the frame, from its start to the end of `bytes`; used to report error offsets
+    pub frame_len: usize,
+    /// Table index (if any) and format string that are being decoded; used to report errors
+    context: (Option<usize>, &'t str),
 }
 
 impl<'t, 'b> Decoder<'t, 'b> {
@@ -35,9 +41,33 @@ impl<'t, 'b> Decoder<'t, 'b> {
             bytes,
             format_list: None,
             below_enum: false,
+            max_len: usize::MAX,
+            frame_len: bytes.len(),
+            context: (None, ""),
         }
     }
 
+    /// Offset of the next byte from the start of the frame
+    pub(crate) fn offset(&self) -> usize {
+        self.frame_len - self.bytes.len()
+    }
+
+    /// Reports malformed data at `offset` in the format string that's being decoded
+    fn malformed(&self, offset: usize, reason: MalformedReason) -> DecodeError {
+        let (index, format) = self.context;
+        malformed(offset, index, format, reason)
+    }
+
+    /// Reads the length prefix of a string or slice
+    fn read_len(&mut self) -> Result<usize, DecodeError> {
+        let offset = self.offset();
+        let len = self.bytes.read_u32::<LE>()? as usize;
+        if len > self.max_len {
+            return Err(self.malformed(offset, MalformedReason::LengthTooLarge(len)));
+        }
+        Ok(len)
+    }
+
     /// Sort and deduplicate `params` so that they can be interpreted correctly during decoding
     fn prepare_params(&self, params: &mut Vec<Parameter>) {
         // deduplicate bitfields by merging them by index
@@ -51,56 +81,65 @@ impl<'t, 'b> Decoder<'t, 'b> {
     /// Gets a format string from
     /// - the `FormatList`, if it's in `Use` mode, or
     /// - from `bytes` and `table` if the `FormatList` is in `Build` mode or was not provided
-    fn get_format(&mut self) -> Result<&'t str, DecodeError> {
+    fn get_format(&mut self) -> Result<(usize, &'t str), DecodeError> {
         if let Some(FormatList::Use { formats, cursor }) = self.format_list.as_mut() {
             if let Some(format) = formats.get(*cursor) {
                 *cursor += 1;
-                return Ok(format);
+                return Ok(*format);
             }
         }
 
+        let offset = self.offset();
         let index = self.bytes.read_u16::<LE>()? as usize;
         let format = self
             .table
             .get_without_level(index as usize)
-            .map_err(|_| DecodeError::Malformed)?;
+            .map_err(|reason| self.malformed(offset, reason))?;
 
         if let Some(FormatList::Build { formats }) = self.format_list.as_mut() {
             if !self.below_enum {
-                formats.push(format)
+                formats.push((index, format))
             }
         }
-        Ok(format)
+        Ok((index, format))
     }
 
-    fn get_variant(&mut self, format: &'t str) -> Result<&'t str, DecodeError> {
+    fn get_variant(&mut self, index: usize, format: &'t str) -> Result<&'t str, DecodeError> {
         assert!(format.contains('|'));
         // NOTE nesting of enums, like "A|B(C|D)" is not possible; indirection is
         // required: "A|B({:?})" where "{:?}" -> "C|D"
         let num_variants = format.chars().filter(|c| *c == '|').count();
+        let offset = self.offset();
+        let unknown_discriminant = |discriminant| {
+            let reason = MalformedReason::UnknownDiscriminant {
+                discriminant,
+                variants: num_variants + 1,
+            };
+            malformed(offset, Some(index), format, reason)
+        };
 
         let discriminant: usize = if u8::try_from(num_variants).is_ok() {
             self.bytes.read_u8()?.into()
         } else if u16::try_from(num_variants).is_ok() {
             self.bytes.read_u16::<LE>()?.into()
         } else if u32::try_from(num_variants).is_ok() {
-            self.bytes
-                .read_u32::<LE>()?
+            let discriminant = self.bytes.read_u32::<LE>()?;
+            discriminant
                 .try_into()
-                .map_err(|_| DecodeError::Malformed)?
+                .map_err(|_| unknown_discriminant(discriminant.into()))?
         } else if u64::try_from(num_variants).is_ok() {
-            self.bytes
-                .read_u64::<LE>()?
+            let discriminant = self.bytes.read_u64::<LE>()?;
+            discriminant
                 .try_into()
-                .map_err(|_| DecodeError::Malformed)?
+                .map_err(|_| unknown_discriminant(discriminant))?
         } else {
-            return Err(DecodeError::Malformed);
+            unreachable!("more than `u64::MAX` enum variants")
         };
 
         format
             .split('|')
             .nth(discriminant)
-            .ok_or(DecodeError::Malformed)
+            .ok_or_else(|| unknown_discriminant(discriminant as u64))
     }
 
     fn decode_format_slice(
@@ -111,7 +150,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
             return Ok(vec![]);
         }
 
-        let format = self.get_format()?;
+        let (index, format) = self.get_format()?;
 
         // let variant_format = if
         let is_enum = format.contains('|');
@@ -128,26 +167,26 @@ impl<'t, 'b> Decoder<'t, 'b> {
             let is_first = i == 0;
 
             let format = if is_enum {
-                self.get_variant(format)?
+                self.get_variant(index, format)?
             } else {
                 format
             };
 
             let args = if let Some(list) = &mut self.format_list {
                 match list {
-                    FormatList::Use { .. } => self.decode_format(format)?,
+                    FormatList::Use { .. } => self.decode_format(Some(index), format)?,
 
                     FormatList::Build { formats } => {
                         if is_first {
                             cursor = formats.len();
-                            self.decode_format(format)?
+                            self.decode_format(Some(index), format)?
                         } else {
                             let formats = formats.clone();
                             let old = mem::replace(
                                 &mut self.format_list,
                                 Some(FormatList::Use { formats, cursor }),
                             );
-                            let args = self.decode_format(format)?;
+                            let args = self.decode_format(Some(index), format)?;
                             self.format_list = old;
                             args
                         }
@@ -156,7 +195,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
             } else if is_first {
                 let mut old =
                     mem::replace(&mut self.format_list, Some(FormatList::Build { formats }));
-                let args = self.decode_format(format)?;
+                let args = self.decode_format(Some(index), format)?;
                 mem::swap(&mut self.format_list, &mut old);
                 formats = match old {
                     Some(FormatList::Build { formats, .. }) => formats,
@@ -169,7 +208,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
                     &mut self.format_list,
                     Some(FormatList::Use { formats, cursor: 0 }),
                 );
-                let args = self.decode_format(format)?;
+                let args = self.decode_format(Some(index), format)?;
                 self.format_list = old;
                 args
             };
@@ -184,11 +223,69 @@ impl<'t, 'b> Decoder<'t, 'b> {
         Ok(elements)
     }
 
+    /// Decodes a `Format` value, `{=?}`; returns its format string, or that of the enum variant,
+    /// and its arguments
+    fn decode_format_value(&mut self) -> Result<(&'t str, Vec<Arg<'t>>), DecodeError> {
+        let (index, format) = self.get_format()?;
+
+        if format.contains('|') {
+            // enum
+            let variant = self.get_variant(index, format)?;
+            let below_enum = self.below_enum;
+            self.below_enum = true;
+            let args = self.decode_format(Some(index), variant)?;
+            self.below_enum = below_enum;
+            Ok((variant, args))
+        } else {
+            let args = self.decode_format(Some(index), format)?;
+            Ok((format, args))
+        }
+    }
+
+    /// Decodes the elements of a `{=__internal_FormatSequence}`: `Format` values, each preceded
+    /// by a `1` byte, terminated by a `0` byte
+    fn decode_format_sequence(&mut self) -> Result<Vec<FormatSliceElement<'t>>, DecodeError> {
+        let mut elements = vec![];
+        loop {
+            let offset = self.offset();
+            match self.bytes.read_u8()? {
+                0 => return Ok(elements),
+                1 => {
+                    let (format, args) = self.decode_format_value()?;
+                    elements.push(FormatSliceElement { format, args });
+                }
+                marker => {
+                    return Err(
+                        self.malformed(offset, MalformedReason::InvalidSequenceMarker(marker))
+                    )
+                }
+            }
+        }
+    }
+
     /// Decodes arguments from the stream, according to `format`.
-    pub fn decode_format(&mut self, format: &str) -> Result<Vec<Arg<'t>>, DecodeError> {
+    ///
+    /// `index` is the table index of `format`, if it has one; it's only used to report errors.
+    pub fn decode_format(
+        &mut self,
+        index: Option<usize>,
+        format: &'t str,
+    ) -> Result<Vec<Arg<'t>>, DecodeError> {
+        let outer = mem::replace(&mut self.context, (index, format));
+        let args = self.decode_args(format);
+        self.context = outer;
+        args
+    }
+
+    fn decode_args(&mut self, format: &'t str) -> Result<Vec<Arg<'t>>, DecodeError> {
         let mut args = vec![]; // will contain the deserialized arguments on return
         let mut params = defmt_parser::parse(format, defmt_parser::ParserMode::ForwardsCompatible)
-            .map_err(|_| DecodeError::Malformed)?
+            .map_err(|e| {
+                self.malformed(
+                    self.offset(),
+                    MalformedReason::InvalidFormatString(e.to_string()),
+                )
+            })?
             .iter()
             .filter_map(|frag| match frag {
                 Fragment::Parameter(param) => Some(param.clone()),
@@ -199,6 +296,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
         self.prepare_params(&mut params);
 
         for param in &params {
+            let offset = self.offset();
             match &param.ty {
                 Type::I8 => args.push(Arg::Ixx(self.bytes.read_i8()? as i128)),
                 Type::I16 => args.push(Arg::Ixx(self.bytes.read_i16::<LE>()? as i128)),
@@ -223,34 +321,31 @@ impl<'t, 'b> Decoder<'t, 'b> {
                 Type::Bool => args.push(Arg::Bool(match self.bytes.read_u8()? {
                     0 => false,
                     1 => true,
-                    _ => return Err(DecodeError::Malformed),
+                    value => {
+                        return Err(self.malformed(offset, MalformedReason::InvalidBool(value)))
+                    }
                 })),
                 Type::FormatSlice => {
-                    let num_elements = self.bytes.read_u32::<LE>()? as usize;
+                    let num_elements = self.read_len()?;
                     let elements = self.decode_format_slice(num_elements)?;
                     args.push(Arg::FormatSlice { elements });
                 }
+                Type::FormatSequence => {
+                    // the elements are always tagged, also in a `{=[?]}` slice, so their formats
+                    // are neither taken from nor added to the format list
+                    let format_list = self.format_list.take();
+                    let elements = self.decode_format_sequence();
+                    self.format_list = format_list;
+                    args.push(Arg::FormatSequence {
+                        elements: elements?,
+                    });
+                }
                 Type::Format => {
-                    let format = self.get_format()?;
-
-                    if format.contains('|') {
-                        // enum
-                        let variant = self.get_variant(format)?;
-                        let below_enum = self.below_enum;
-                        self.below_enum = true;
-                        let inner_args = self.decode_format(variant)?;
-                        self.below_enum = below_enum;
-                        args.push(Arg::Format {
-                            format: variant,
-                            args: inner_args,
-                        });
-                    } else {
-                        let inner_args = self.decode_format(format)?;
-                        args.push(Arg::Format {
-                            format,
-                            args: inner_args,
-                        });
-                    }
+                    let (format, inner_args) = self.decode_format_value()?;
+                    args.push(Arg::Format {
+                        format,
+                        args: inner_args,
+                    });
                 }
                 Type::BitField(range) => {
                     let mut data: u128;
@@ -273,7 +368,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
                     args.push(Arg::Uxx(data));
                 }
                 Type::Str => {
-                    let str_len = self.bytes.read_u32::<LE>()? as usize;
+                    let str_len = self.read_len()?;
                     let mut arg_str_bytes = vec![];
 
                     // note: went for the suboptimal but simple solution; optimize if necessary
@@ -282,8 +377,8 @@ impl<'t, 'b> Decoder<'t, 'b> {
                     }
 
                     // convert to utf8 (no copy)
-                    let arg_str =
-                        String::from_utf8(arg_str_bytes).map_err(|_| DecodeError::Malformed)?;
+                    let arg_str = String::from_utf8(arg_str_bytes)
+                        .map_err(|_| self.malformed(offset, MalformedReason::InvalidUtf8))?;
 
                     args.push(Arg::Str(arg_str));
                 }
@@ -293,13 +388,13 @@ impl<'t, 'b> Decoder<'t, 'b> {
                     let string = self
                         .table
                         .get_without_level(str_index as usize)
-                        .map_err(|_| DecodeError::Malformed)?;
+                        .map_err(|reason| self.malformed(offset, reason))?;
 
                     args.push(Arg::IStr(string));
                 }
                 Type::U8Slice => {
                     // only supports byte slices
-                    let num_elements = self.bytes.read_u32::<LE>()? as usize;
+                    let num_elements = self.read_len()?;
                     let mut arg_slice = vec![];
 
                     // note: went for the suboptimal but simple solution; optimize if necessary
@@ -322,7 +417,9 @@ impl<'t, 'b> Decoder<'t, 'b> {
                 }
                 Type::Char => {
                     let data = self.bytes.read_u32::<LE>()?;
-                    let c = std::char::from_u32(data).ok_or(DecodeError::Malformed)?;
+                    let c = std::char::from_u32(data).ok_or_else(|| {
+                        self.malformed(offset, MalformedReason::InvalidChar(data))
+                    })?;
                     args.push(Arg::Char(c));
                 }
                 Type::Debug | Type::Display => {
@@ -334,7 +431,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
                         .position(|b| *b == 0xff)
                         .ok_or(DecodeError::UnexpectedEof)?;
                     let data = core::str::from_utf8(&self.bytes[..end])
-                        .map_err(|_| DecodeError::Malformed)?;
+                        .map_err(|_| self.malformed(offset, MalformedReason::InvalidUtf8))?;
                     self.bytes = &self.bytes[end + 1..];
 
                     args.push(Arg::Preformatted(data.into()));
@@ -346,6 +443,20 @@ impl<'t, 'b> Decoder<'t, 'b> {
     }
 }
 
+fn malformed(
+    offset: usize,
+    index: Option<usize>,
+    format: &str,
+    reason: MalformedReason,
+) -> DecodeError {
+    DecodeError::Malformed(MalformedFrame {
+        offset,
+        index,
+        format: Some(format.to_owned()),
+        reason,
+    })
+}
+
 /// Note that this will not change the Bitfield params in place, i.e. if `params` was sorted before
 /// a call to this function, it won't be afterwards.
 fn merge_bitfields(params: &mut Vec<Parameter>) {
@@ -0,0 +1,131 @@
+//! Dictionary files: a self-contained copy of a [`Table`] and its [`Locations`]
+//!
+//! A dictionary is a JSON document that can be stored next to log captures and used to decode them
+//! long after the firmware's ELF file is gone.
+
+use std::io;
+
+use anyhow::{bail, Context as _};
+use serde::{Deserialize, Serialize};
+
+use crate::{elf2table::check_version, Locations, Table, DEFMT_VERSION};
+
+/// Version of the dictionary file format; bump this when making incompatible changes to it
+const DICTIONARY_VERSION: u32 = 1;
+
+/// The fields that are read before the rest of the dictionary
+#[derive(Deserialize)]
+struct Header {
+    dictionary_version: u32,
+    defmt_version: String,
+}
+
+#[derive(Serialize)]
+struct DictionaryRef<'a> {
+    dictionary_version: u32,
+    defmt_version: &'a str,
+    table: &'a Table,
+    locations: Option<&'a Locations>,
+}
+
+#[derive(Deserialize)]
+struct Dictionary {
+    table: Table,
+    locations: Option<Locations>,
+}
+
+pub(crate) fn write(
+    table: &Table,
+    locations: Option<&Locations>,
+    writer: impl io::Write,
+) -> Result<(), anyhow::Error> {
+    let dictionary = DictionaryRef {
+        dictionary_version: DICTIONARY_VERSION,
+        defmt_version: DEFMT_VERSION,
+        table,
+        locations,
+    };
+    serde_json::to_writer(writer, &dictionary)?;
+    Ok(())
+}
+
+pub(crate) fn parse(dictionary: &[u8]) -> Result<(Table, Option<Locations>), anyhow::Error> {
+    let header =
+        serde_json::from_slice::<Header>(dictionary).context("not a defmt dictionary file")?;
+    if header.dictionary_version != DICTIONARY_VERSION {
+        bail!(
+            "unsupported dictionary file format version {} (expected {})",
+            header.dictionary_version,
+            DICTIONARY_VERSION
+        );
+    }
+    check_version(&header.defmt_version).map_err(anyhow::Error::msg)?;
+
+    let dictionary = serde_json::from_slice::<Dictionary>(dictionary)
+        .context("malformed defmt dictionary file")?;
+    Ok((dictionary.table, dictionary.locations))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use super::*;
+    use crate::{Encoding, Location, TableEntry, Tag};
+
+    #[test]
+    fn roundtrip() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            4,
+            TableEntry::new_without_symbol(Tag::Warn, "x={=u8}".to_owned()),
+        );
+        let mut table = Table::new(entries);
+        table.set_timestamp_entry(TableEntry::new_without_symbol(
+            Tag::Timestamp,
+            "{=u8:µs}".to_owned(),
+        ));
+        table.set_encoding(Encoding::Rzcobs);
+
+        let mut locations = Locations::new();
+        locations.insert(
+            4,
+            Location {
+                file: PathBuf::from("src/main.rs"),
+                line: 12,
+                module: "app".to_owned(),
+            },
+        );
+
+        let mut dictionary = vec![];
+        table
+            .write_dictionary(Some(&locations), &mut dictionary)
+            .unwrap();
+        let (table, locations) = Table::parse_dictionary(&dictionary).unwrap();
+
+        assert_eq!(table.encoding(), Encoding::Rzcobs);
+        let (frame, _) = table.decode(&[4, 0, 7, 42]).unwrap();
+        assert_eq!(frame.display(false).to_string(), "0.000007 WARN x=42");
+
+        let location = &locations.unwrap()[&4];
+        assert_eq!(location.file, PathBuf::from("src/main.rs"));
+        assert_eq!(location.line, 12);
+        assert_eq!(location.module, "app");
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let dictionary = br#"{
+            "dictionary_version": 1,
+            "defmt_version": "0.0",
+            "table": { "timestamp": null, "entries": {}, "encoding": "Raw" },
+            "locations": null
+        }"#;
+        let error = Table::parse_dictionary(dictionary).unwrap_err();
+        assert!(error.to_string().contains("defmt version mismatch"));
+
+        let dictionary = br#"{ "dictionary_version": 2, "defmt_version": "0.0" }"#;
+        let error = Table::parse_dictionary(dictionary).unwrap_err();
+        assert!(error.to_string().contains("format version 2"));
+    }
+}
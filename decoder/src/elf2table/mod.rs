@@ -12,17 +12,29 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::{StringEntry, Table, TableEntry, Tag, DEFMT_VERSION};
+use crate::{Encoding, StringEntry, Table, TableEntry, Tag, DEFMT_VERSION};
 use anyhow::{anyhow, bail, ensure};
 use object::{Object, ObjectSection, ObjectSymbol};
+use serde::{Deserialize, Serialize};
+
+/// Returns the value of the linker script symbol `name` if it is the `symbol = value` symbol
+///
+/// Note that we check for a quoted and unquoted symbol, since LLD has a bug that makes it keep
+/// the quotes from the linker script.
+fn linker_symbol_value<'a>(name: &'a str, symbol: &str) -> Option<&'a str> {
+    let name = name.strip_prefix('"').unwrap_or(name);
+    let value = name.strip_prefix(symbol)?.strip_prefix(" = ")?;
+    Some(value.trim_end_matches('"'))
+}
 
 pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyhow::Error> {
     let elf = object::File::parse(elf)?;
-    // first pass to extract the `_defmt_version`
+    // first pass to extract the `_defmt_version`, `_defmt_encoding_`, `_defmt_crc_` and
+    // `_defmt_sequence_numbers_`
     let mut version = None;
-    let is_defmt_version = |name: &str| {
-        name.starts_with("\"_defmt_version_ = ") || name.starts_with("_defmt_version_ = ")
-    };
+    let mut encoding = None;
+    let mut sequence_numbers = None;
+    let mut crc = None;
     for entry in elf.symbols() {
         let name = match entry.name() {
             Ok(name) => name,
@@ -31,13 +43,7 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
 
         // Not in the `.defmt` section because it's not tied to the address of any symbol
         // in `.defmt`.
-        // Note that we check for a quoted and unquoted version symbol, since LLD has a bug that
-        // makes it keep the quotes from the linker script.
-        if is_defmt_version(name) {
-            let new_version = name
-                .trim_start_matches("\"_defmt_version_ = ")
-                .trim_start_matches("_defmt_version_ = ")
-                .trim_end_matches('"');
+        if let Some(new_version) = linker_symbol_value(name, "_defmt_version_") {
             if let Some(version) = version {
                 return Err(anyhow!(
                     "multiple defmt versions in use: {} and {} (only one is supported)",
@@ -47,6 +53,39 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
             }
             version = Some(new_version);
         }
+
+        if let Some(new_encoding) = linker_symbol_value(name, "_defmt_encoding_") {
+            if encoding.is_some() {
+                bail!("multiple defmt encodings specified (only one is supported)");
+            }
+            encoding = Some(match new_encoding {
+                "raw" => Encoding::Raw,
+                "rzcobs" => Encoding::Rzcobs,
+                _ => bail!("unknown defmt encoding `{}`", new_encoding),
+            });
+        }
+
+        if let Some(new_crc) = linker_symbol_value(name, "_defmt_crc_") {
+            if crc.is_some() {
+                bail!("multiple defmt CRC settings specified (only one is supported)");
+            }
+            crc = Some(match new_crc {
+                "crc16" => true,
+                "none" => false,
+                _ => bail!("unknown defmt CRC `{}`", new_crc),
+            });
+        }
+
+        if let Some(new_sequence_numbers) = linker_symbol_value(name, "_defmt_sequence_numbers_") {
+            if sequence_numbers.is_some() {
+                bail!("multiple defmt sequence number settings specified (only one is supported)");
+            }
+            sequence_numbers = Some(match new_sequence_numbers {
+                "u8" => true,
+                "none" => false,
+                _ => bail!("unknown defmt sequence numbers `{}`", new_sequence_numbers),
+            });
+        }
     }
 
     // NOTE: We need to make sure to return `Ok(None)`, not `Err`, when defmt is not in use.
@@ -74,6 +113,8 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
     // second pass to demangle symbols
     let mut map = BTreeMap::new();
     let mut timestamp = None;
+    let mut build_id = 0u32;
+    let mut has_build_id = false;
     for entry in elf.symbols() {
         // Skipping symbols with empty string names, as they may be added by
         // `objcopy`, and breaks JSON demangling
@@ -82,7 +123,15 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
             _ => continue,
         };
 
-        if is_defmt_version(name) || name.starts_with("__DEFMT_MARKER") {
+        let is_setting = [
+            "_defmt_version_",
+            "_defmt_encoding_",
+            "_defmt_crc_",
+            "_defmt_sequence_numbers_",
+        ]
+        .iter()
+        .any(|symbol| linker_symbol_value(name, symbol).is_some());
+        if is_setting || name.starts_with("__DEFMT_MARKER") {
             // `_defmt_version_` is not a JSON encoded `defmt` symbol / log-message; skip it
             // LLD and GNU LD behave differently here. LLD doesn't include `_defmt_version_`
             // (defined in a linker script) in the `.defmt` section but GNU LD does.
@@ -93,6 +142,11 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
             let sym = symbol::Symbol::demangle(name)?;
             match sym.tag() {
                 symbol::SymbolTag::Defmt(tag) => {
+                    // every string interned by `defmt` is part of the build ID
+                    build_id =
+                        build_id.wrapping_add(build_id_contribution(entry.address() as u16, name));
+                    has_build_id |= tag == Tag::BuildId;
+
                     if tag == Tag::Timestamp {
                         if timestamp.is_some() {
                             bail!("multiple timestamp format specifications found");
@@ -106,7 +160,8 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
                         map.insert(
                             entry.address() as usize,
                             TableEntry::new(
-                                StringEntry::new(tag, sym.data().to_string()),
+                                StringEntry::new(tag, sym.data().to_string())
+                                    .with_fields(sym.fields().to_vec()),
                                 name.to_string(),
                             ),
                         );
@@ -121,11 +176,36 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
     if let Some(ts) = timestamp {
         table.set_timestamp_entry(ts);
     }
+    // firmware built with older versions of `defmt` doesn't specify an encoding; it's raw
+    table.set_encoding(encoding.unwrap_or(Encoding::Raw));
+    // likewise, it doesn't send sequence numbers
+    table.set_sequence_numbers(sequence_numbers.unwrap_or(false));
+    table.set_crc(crc.unwrap_or(false));
+    if has_build_id {
+        table.set_build_id(build_id);
+    }
     Ok(Some(table))
 }
 
+/// Contribution of the string with `index` and raw symbol `symbol` to the build ID; the build ID
+/// is the (wrapping) sum of the contributions of all strings
+///
+/// This must match the computation in the `defmt` crate (`build_id` module)
+fn build_id_contribution(index: u16, symbol: &str) -> u32 {
+    let mut bytes = index.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&fnv1a(symbol.as_bytes()).to_le_bytes());
+    fnv1a(&bytes)
+}
+
+/// 32-bit FNV-1a
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
 /// Checks if the version encoded in the symbol table is compatible with this version of the `decoder` crate
-fn check_version(version: &str) -> Result<(), String> {
+pub fn check_version(version: &str) -> Result<(), String> {
     enum Kind {
         /// `1` or `0.1`
         Semver,
@@ -179,7 +259,7 @@ panic-probe = {{ git = \"https://github.com/knurling-rs/defmt\", features = [\"p
 }
 
 /// Location of a defmt log statement in the elf-file
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Location {
     pub file: PathBuf,
     pub line: u64,
@@ -392,3 +472,24 @@ fn exprloc2address<R: gimli::read::Reader<Offset = usize>>(
 
     Err(anyhow!("`Operation::Address` not found"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_id_contribution() {
+        assert_eq!(fnv1a(b"a"), 0xe40c_292c);
+        assert_eq!(super::build_id_contribution(3, "a"), 0xddd9_d89d);
+    }
+
+    #[test]
+    fn linker_symbol_value() {
+        let value = |name| super::linker_symbol_value(name, "_defmt_crc_");
+        assert_eq!(value("_defmt_crc_ = crc16"), Some("crc16"));
+        // LLD keeps the quotes of the linker script
+        assert_eq!(value("\"_defmt_crc_ = none\""), Some("none"));
+        assert_eq!(value("_defmt_crc_x = none"), None);
+        assert_eq!(value("_defmt_version_ = 1"), None);
+    }
+}
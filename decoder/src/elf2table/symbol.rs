@@ -14,6 +14,7 @@ pub struct Symbol {
     /// * `defmt_prim` for primitive formatting strings that are placed at the start of the `.defmt`
     ///   section.
     /// * `defmt_fmt`, `defmt_str` for interned format strings and string literals.
+    /// * `defmt_build_id` for the frame that carries the build ID (`build-id` feature).
     /// * `defmt_trace`, `defmt_debug`, `defmt_info`, `defmt_warn`, `defmt_error` for logging
     ///   messages used at the different log levels.
     /// * Anything starting with `defmt_` is reserved for use by defmt, other prefixes are free for
@@ -22,6 +23,10 @@ pub struct Symbol {
 
     /// Symbol data for use by the host tooling. Interpretation depends on `tag`.
     data: String,
+
+    /// Names of the key-value fields of a log statement, e.g. `info!("connected", peer = addr)`
+    #[serde(default)]
+    fields: Vec<String>,
 }
 
 pub enum SymbolTag<'a> {
@@ -43,7 +48,9 @@ impl Symbol {
             "defmt_derived" => SymbolTag::Defmt(Tag::Derived),
             "defmt_write" => SymbolTag::Defmt(Tag::Write),
             "defmt_timestamp" => SymbolTag::Defmt(Tag::Timestamp),
+            "defmt_build_id" => SymbolTag::Defmt(Tag::BuildId),
             "defmt_str" => SymbolTag::Defmt(Tag::Str),
+            "defmt_span" => SymbolTag::Defmt(Tag::Span),
             "defmt_trace" => SymbolTag::Defmt(Tag::Trace),
             "defmt_debug" => SymbolTag::Defmt(Tag::Debug),
             "defmt_info" => SymbolTag::Defmt(Tag::Info),
@@ -56,4 +63,8 @@ impl Symbol {
     pub fn data(&self) -> &str {
         &self.data
     }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
 }
@@ -1,12 +1,13 @@
 use std::{
     convert::TryFrom,
     fmt::{self, Write as _},
-    mem,
+    mem, slice,
 };
 
-use crate::Arg;
+use crate::{Arg, Args, Value};
 use colored::Colorize;
 use defmt_parser::{DisplayHint, Fragment, Level, ParserMode, Type};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 
 /// A log frame
 #[derive(Debug, PartialEq)]
@@ -18,6 +19,37 @@ pub struct Frame<'t> {
     // Format string
     format: &'t str,
     args: Vec<Arg<'t>>,
+    /// Key-value fields, e.g. `info!("connected", peer = addr)`
+    fields: Vec<(&'t str, Arg<'t>)>,
+    /// Set if this is the enter or exit frame of a span
+    span: Option<SpanEvent>,
+    /// Number of spans that were open when this frame was logged
+    depth: usize,
+    sequence_number: Option<u8>,
+    lost_frames: usize,
+}
+
+/// What the frame of a span (see `defmt::span!`) marks
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SpanEvent {
+    /// The span has been entered
+    Enter { id: u16 },
+    /// The span has been exited
+    ///
+    /// `elapsed` is the time spent in the span, in the unit of the timestamp. It is only known
+    /// when the enter frame was decoded by the same [`StreamDecoder`](crate::StreamDecoder) and
+    /// the timestamp is a single integer.
+    Exit { id: u16, elapsed: Option<u128> },
+}
+
+impl SpanEvent {
+    /// Returns the ID of the span, which is shared by its enter and exit frames
+    pub fn id(&self) -> u16 {
+        match self {
+            SpanEvent::Enter { id } | SpanEvent::Exit { id, .. } => *id,
+        }
+    }
 }
 
 impl<'t> Frame<'t> {
@@ -36,9 +68,42 @@ impl<'t> Frame<'t> {
             timestamp_args,
             format,
             args,
+            fields: vec![],
+            span: None,
+            depth: 0,
+            sequence_number: None,
+            lost_frames: 0,
+        }
+    }
+
+    pub(crate) fn set_fields(&mut self, fields: Vec<(&'t str, Arg<'t>)>) {
+        self.fields = fields;
+    }
+
+    pub(crate) fn set_span(&mut self, span: Option<SpanEvent>) {
+        self.span = span;
+    }
+
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    /// Returns the raw timestamp if it's a single integer, e.g. `{=u64:µs}`
+    pub(crate) fn timestamp_ticks(&self) -> Option<u128> {
+        match self.timestamp_args.as_slice() {
+            [Arg::Uxx(ticks)] => Some(*ticks),
+            _ => None,
         }
     }
 
+    pub(crate) fn set_sequence_number(&mut self, sequence_number: Option<u8>) {
+        self.sequence_number = sequence_number;
+    }
+
+    pub(crate) fn set_lost_frames(&mut self, lost_frames: usize) {
+        self.lost_frames = lost_frames;
+    }
+
     /// Returns a struct that will format this log frame (including message, timestamp, level,
     /// etc.).
     pub fn display(&'t self, colored: bool) -> DisplayFrame<'t> {
@@ -52,14 +117,24 @@ impl<'t> Frame<'t> {
         self.timestamp_format.map(|fmt| DisplayMessage {
             format: fmt,
             args: &self.timestamp_args,
+            fields: &[],
+            span: None,
+            timestamp_format: None,
         })
     }
 
-    /// Returns a struct that will format the message contained in this log frame.
+    /// Returns a struct that will format the message contained in this log frame, followed by its
+    /// fields as `key=value`.
+    ///
+    /// The name of a span is prefixed with `→` in its enter frame and with `←` in its exit frame;
+    /// the latter is followed by the time spent in the span, if known.
     pub fn display_message(&'t self) -> DisplayMessage<'t> {
         DisplayMessage {
             format: self.format,
             args: &self.args,
+            fields: &self.fields,
+            span: self.span,
+            timestamp_format: self.timestamp_format,
         }
     }
 
@@ -70,17 +145,186 @@ impl<'t> Frame<'t> {
     pub fn index(&self) -> u64 {
         self.index
     }
+
+    /// Returns the format string of the message
+    pub fn format(&self) -> &'t str {
+        self.format
+    }
+
+    /// Iterates over the arguments of the message
+    pub fn args(&self) -> Args<'_> {
+        Args::new(&self.args)
+    }
+
+    /// Iterates over the key-value fields of the log statement, e.g. `peer` and `rssi` in
+    /// `info!("connected", peer = addr, rssi = r)`, in the order in which they were written
+    pub fn fields(&self) -> impl ExactSizeIterator<Item = (&'t str, Value<'_>)> + '_ {
+        self.fields
+            .iter()
+            .map(|(name, arg)| (*name, Value::new(arg)))
+    }
+
+    /// Returns the value of the field `name`, if the log statement has one
+    pub fn field(&self, name: &str) -> Option<Value<'_>> {
+        self.fields()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the enter or exit event if this is a frame of a span
+    pub fn span(&self) -> Option<SpanEvent> {
+        self.span
+    }
+
+    /// Returns the number of spans that were open when this frame was logged; the enter and exit
+    /// frames of a span have the same depth
+    ///
+    /// This is only known when the frame was decoded by a [`StreamDecoder`](crate::StreamDecoder);
+    /// it is `0` otherwise.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the format string of the timestamp, if the firmware has one
+    pub fn timestamp_format(&self) -> Option<&'t str> {
+        self.timestamp_format
+    }
+
+    /// Iterates over the arguments of the timestamp
+    pub fn timestamp_args(&self) -> Args<'_> {
+        Args::new(&self.timestamp_args)
+    }
+
+    /// Returns the sequence number of the frame, if the firmware sends them (`sequence-numbers`
+    /// feature of `defmt`)
+    pub fn sequence_number(&self) -> Option<u8> {
+        self.sequence_number
+    }
+
+    /// Returns the number of frames that were lost between the previous frame and this one
+    ///
+    /// This is only known when the frame was decoded by a [`StreamDecoder`](crate::StreamDecoder)
+    /// and the firmware sends sequence numbers; it is `0` otherwise. Sequence numbers wrap around
+    /// after 255 so larger gaps are under-reported.
+    pub fn lost_frames(&self) -> usize {
+        self.lost_frames
+    }
+}
+
+/// Serializes the frame as
+///
+/// ``` text
+/// {
+///   "index": 2,
+///   "level": "info",
+///   "timestamp": { "rendered": "0.000123", "raw": [123] },
+///   "message": "x=42",
+///   "format": "x={=u8}",
+///   "args": [42],
+///   "fields": { "peer": 3 },
+///   "span": null,
+///   "depth": 0,
+///   "sequence_number": 7,
+///   "lost_frames": 0
+/// }
+/// ```
+///
+/// `timestamp` and `sequence_number` are `null` if the firmware doesn't have them. See [`Value`](crate::Value) for how
+/// the arguments are serialized. The `span` of an enter frame is `{ "kind": "enter", "id": 1 }`,
+/// that of an exit frame `{ "kind": "exit", "id": 1, "elapsed": 250 }`.
+impl Serialize for Frame<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let timestamp = self.display_timestamp().map(|display| Timestamp {
+            rendered: display.to_string(),
+            frame: self,
+        });
+
+        let mut frame = serializer.serialize_struct("Frame", 11)?;
+        frame.serialize_field("index", &self.index)?;
+        frame.serialize_field("level", self.level.as_str())?;
+        frame.serialize_field("timestamp", &timestamp)?;
+        frame.serialize_field("message", &self.display_message().to_string())?;
+        frame.serialize_field("format", self.format)?;
+        frame.serialize_field("args", &self.args().collect::<Vec<_>>())?;
+        frame.serialize_field("fields", &FieldsMap(self))?;
+        frame.serialize_field("span", &self.span)?;
+        frame.serialize_field("depth", &self.depth)?;
+        frame.serialize_field("sequence_number", &self.sequence_number)?;
+        frame.serialize_field("lost_frames", &self.lost_frames)?;
+        frame.end()
+    }
+}
+
+struct Timestamp<'f> {
+    rendered: String,
+    frame: &'f Frame<'f>,
+}
+
+impl Serialize for Timestamp<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut timestamp = serializer.serialize_struct("Timestamp", 2)?;
+        timestamp.serialize_field("rendered", &self.rendered)?;
+        timestamp.serialize_field("raw", &self.frame.timestamp_args().collect::<Vec<_>>())?;
+        timestamp.end()
+    }
+}
+
+/// Serializes the fields of a frame as a map from their names to their values
+struct FieldsMap<'f>(&'f Frame<'f>);
+
+impl Serialize for FieldsMap<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(self.0.fields())
+    }
 }
 
 pub struct DisplayMessage<'t> {
     format: &'t str,
     args: &'t [Arg<'t>],
+    fields: &'t [(&'t str, Arg<'t>)],
+    span: Option<SpanEvent>,
+    timestamp_format: Option<&'t str>,
 }
 
 impl fmt::Display for DisplayMessage<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(SpanEvent::Enter { .. }) => f.write_str("→ ")?,
+            Some(SpanEvent::Exit { .. }) => f.write_str("← ")?,
+            None => {}
+        }
         let args = format_args(self.format, self.args, None);
-        f.write_str(&args)
+        f.write_str(&args)?;
+        if let Some(SpanEvent::Exit {
+            elapsed: Some(elapsed),
+            ..
+        }) = self.span
+        {
+            // render the elapsed time like a timestamp, e.g. `{=u64:µs}` -> `0.000250`
+            let elapsed = match self.timestamp_format {
+                Some(format) => format_args(format, &[Arg::Uxx(elapsed)], None),
+                None => elapsed.to_string(),
+            };
+            write!(f, " ({})", elapsed)?;
+        }
+        for (name, value) in self.fields {
+            write!(
+                f,
+                " {}={}",
+                name,
+                format_args("{=?}", slice::from_ref(value), None)
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -116,9 +360,19 @@ impl fmt::Display for DisplayFrame<'_> {
             .timestamp_format
             .map(|fmt| format!("{} ", format_args(&fmt, &self.frame.timestamp_args, None,)))
             .unwrap_or_default();
-        let args = format_args(&self.frame.format, &self.frame.args, None);
+        let message = self.frame.display_message();
+        // nest the frames logged inside of spans
+        let indent = self.frame.depth * 2;
 
-        write!(f, "{}{} {}", timestamp, level, args)
+        write!(
+            f,
+            "{}{} {:indent$}{}",
+            timestamp,
+            level,
+            "",
+            message,
+            indent = indent
+        )
     }
 }
 
@@ -329,6 +583,16 @@ fn format_args_real(
                             }
                         }
                     }
+                    Arg::FormatSequence { elements } => {
+                        let mut is_first = true;
+                        for element in elements {
+                            if !is_first {
+                                buf.write_str(", ")?;
+                            }
+                            is_first = false;
+                            buf.write_str(&format_args(element.format, &element.args, hint))?;
+                        }
+                    }
                     Arg::Slice(x) => format_bytes(x, hint, &mut buf)?,
                     Arg::Char(c) => write!(buf, "{}", c)?,
                 }
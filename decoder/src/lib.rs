@@ -13,10 +13,15 @@
 // load DEFMT_VERSION
 include!(concat!(env!("OUT_DIR"), "/version.rs"));
 
+mod crc;
 mod decoder;
+mod dictionary;
 mod elf2table;
 mod frame;
 pub mod log;
+mod rzcobs;
+mod stream;
+mod value;
 
 use std::{collections::BTreeMap, error::Error, fmt, io};
 
@@ -24,12 +29,15 @@ use byteorder::{ReadBytesExt, LE};
 use decoder::Decoder;
 use defmt_parser::Level;
 use elf2table::parse_impl;
+use serde::{Deserialize, Serialize};
 
 pub use elf2table::{Location, Locations};
-pub use frame::Frame;
+pub use frame::{Frame, SpanEvent};
+pub use stream::StreamDecoder;
+pub use value::{Args, Fields, FormatValue, Value};
 
 /// Specifies the origin of a format string
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
 pub enum Tag {
     /// Defmt-controlled format string for primitive types.
     Prim,
@@ -41,6 +49,10 @@ pub enum Tag {
     Str,
     /// Defines the global timestamp format.
     Timestamp,
+    /// The build ID frame the firmware sends ahead of its first log frame.
+    BuildId,
+    /// The name of a span; its enter and exit frames are logged at the `TRACE` level.
+    Span,
 
     Trace,
     Debug,
@@ -52,9 +64,9 @@ pub enum Tag {
 impl Tag {
     fn to_level(&self) -> Option<Level> {
         match self {
-            Tag::Trace => Some(Level::Trace),
+            Tag::Trace | Tag::Span => Some(Level::Trace),
+            Tag::BuildId | Tag::Info => Some(Level::Info),
             Tag::Debug => Some(Level::Debug),
-            Tag::Info => Some(Level::Info),
             Tag::Warn => Some(Level::Warn),
             Tag::Error => Some(Level::Error),
             _ => None,
@@ -63,7 +75,7 @@ impl Tag {
 }
 
 /// Entry in [`Table`] combining a format string with it's raw symbol
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct TableEntry {
     string: StringEntry,
     raw_symbol: String,
@@ -84,23 +96,57 @@ impl TableEntry {
 }
 
 /// A format string and it's [`Tag`]
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct StringEntry {
     tag: Tag,
     string: String,
+    /// Names of the key-value fields that follow the arguments of the format string
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<String>,
 }
 
 impl StringEntry {
     pub fn new(tag: Tag, string: String) -> Self {
-        Self { tag, string }
+        Self {
+            tag,
+            string,
+            fields: vec![],
+        }
+    }
+
+    /// Sets the names of the key-value fields of a log statement
+    pub fn with_fields(mut self, fields: Vec<String>) -> Self {
+        self.fields = fields;
+        self
     }
 }
 
 /// Internal table that holds log levels and maps format strings to indices
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Table {
     timestamp: Option<TableEntry>,
     entries: BTreeMap<usize, TableEntry>,
+    encoding: Encoding,
+    /// Build ID of the firmware; `None` if it was built without the `build-id` feature of `defmt`
+    #[serde(default)]
+    build_id: Option<u32>,
+    /// Whether the frame header contains a sequence number (`sequence-numbers` feature of
+    /// `defmt`)
+    #[serde(default)]
+    sequence_numbers: bool,
+    /// Whether every frame ends with a CRC (`crc` feature of `defmt`)
+    #[serde(default)]
+    crc: bool,
+}
+
+/// How log frames are put on the wire; this is selected with the `encoding-*` features of the
+/// `defmt` crate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Encoding {
+    /// Frames are sent back-to-back, without framing
+    Raw,
+    /// Each frame is rzCOBS encoded and terminated by a `0x00` byte
+    Rzcobs,
 }
 
 impl Table {
@@ -109,6 +155,10 @@ impl Table {
         Self {
             entries,
             timestamp: None,
+            encoding: Encoding::Raw,
+            build_id: None,
+            sequence_numbers: false,
+            crc: false,
         }
     }
 
@@ -126,26 +176,93 @@ impl Table {
         parse_impl(elf, false)
     }
 
+    /// Parses a dictionary file written by [`Table::write_dictionary`].
+    ///
+    /// Returns the table and the locations of its log statements, if the dictionary includes them.
+    pub fn parse_dictionary(
+        dictionary: &[u8],
+    ) -> Result<(Table, Option<Locations>), anyhow::Error> {
+        dictionary::parse(dictionary)
+    }
+
+    /// Writes a dictionary file: everything needed to decode the logs of the firmware, without
+    /// the ELF file.
+    ///
+    /// The table must have been obtained with [`Table::parse`] (or from a dictionary) so that its
+    /// version matches the decoder.
+    pub fn write_dictionary(
+        &self,
+        locations: Option<&Locations>,
+        writer: impl io::Write,
+    ) -> Result<(), anyhow::Error> {
+        dictionary::write(self, locations, writer)
+    }
+
     pub fn set_timestamp_entry(&mut self, timestamp: TableEntry) {
         self.timestamp = Some(timestamp);
     }
 
-    fn _get(&self, index: usize) -> Result<(Option<Level>, &str), ()> {
-        let entry = self.entries.get(&index).ok_or(())?;
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Returns the encoding of the log frames sent by the firmware
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn set_build_id(&mut self, build_id: u32) {
+        self.build_id = Some(build_id);
+    }
+
+    /// Returns the build ID of the firmware, if it was built with the `build-id` feature of
+    /// `defmt`
+    ///
+    /// The firmware sends its build ID ahead of its first log frame; `decode` checks it against
+    /// this one.
+    pub fn build_id(&self) -> Option<u32> {
+        self.build_id
+    }
+
+    pub fn set_sequence_numbers(&mut self, sequence_numbers: bool) {
+        self.sequence_numbers = sequence_numbers;
+    }
+
+    /// Returns whether the firmware sends a sequence number with every log frame
+    pub fn has_sequence_numbers(&self) -> bool {
+        self.sequence_numbers
+    }
+
+    pub fn set_crc(&mut self, crc: bool) {
+        self.crc = crc;
+    }
+
+    /// Returns whether the firmware appends a CRC to every log frame
+    ///
+    /// If it does, `decode` reports frames whose CRC doesn't match as malformed.
+    pub fn has_crc(&self) -> bool {
+        self.crc
+    }
+
+    fn _get(&self, index: usize) -> Result<(Option<Level>, &str), MalformedReason> {
+        let entry = self
+            .entries
+            .get(&index)
+            .ok_or(MalformedReason::UnknownIndex(index))?;
         Ok((entry.string.tag.to_level(), &entry.string.string))
     }
 
-    fn get_with_level(&self, index: usize) -> Result<(Level, &str), ()> {
+    fn get_with_level(&self, index: usize) -> Result<(Level, &str), MalformedReason> {
         let (lvl, format) = self._get(index)?;
-        Ok((lvl.ok_or(())?, format))
+        Ok((lvl.ok_or(MalformedReason::NotALogStatement(index))?, format))
     }
 
-    fn get_without_level(&self, index: usize) -> Result<&str, ()> {
+    fn get_without_level(&self, index: usize) -> Result<&str, MalformedReason> {
         let (lvl, format) = self._get(index)?;
         if lvl.is_none() {
             Ok(format)
         } else {
-            Err(())
+            Err(MalformedReason::UnexpectedLogStatement(index))
         }
     }
 
@@ -177,33 +294,201 @@ impl Table {
     /// * bytes: contains the data sent by the device that logs.
     ///          contains the [log string index, timestamp, optional fmt string args]
     pub fn decode<'t>(
+        &'t self,
+        bytes: &[u8],
+    ) -> Result<(Frame<'t>, /*consumed: */ usize), DecodeError> {
+        self.decode_impl(bytes, usize::MAX)
+    }
+
+    fn decode_impl<'t>(
         &'t self,
         mut bytes: &[u8],
+        max_len: usize,
     ) -> Result<(Frame<'t>, /*consumed: */ usize), DecodeError> {
+        let frame_bytes = bytes;
         let len = bytes.len();
-        let index = bytes.read_u16::<LE>()? as u64;
+        let index = bytes.read_u16::<LE>()? as usize;
+
+        let (level, format) = self.get_with_level(index).map_err(|reason| {
+            DecodeError::Malformed(MalformedFrame {
+                offset: 0,
+                index: Some(index),
+                format: None,
+                reason,
+            })
+        })?;
+
+        let sequence_number = if self.sequence_numbers {
+            Some(bytes.read_u8()?)
+        } else {
+            None
+        };
 
         let mut decoder = Decoder::new(self, bytes);
+        decoder.max_len = max_len;
+        decoder.frame_len = len;
 
         let mut timestamp_format = None;
         let mut timestamp_args = Vec::new();
         if let Some(entry) = self.timestamp.as_ref() {
             let format = &entry.string.string;
             timestamp_format = Some(&**format);
-            timestamp_args = decoder.decode_format(format)?;
+            timestamp_args = decoder.decode_format(None, format)?;
         }
 
-        let (level, format) = self
-            .get_with_level(index as usize)
-            .map_err(|_| DecodeError::Malformed)?;
+        let args_offset = decoder.offset();
+        let args = decoder.decode_format(Some(index), format)?;
+
+        // the frame kind and the ID of a span follow its (argument-less) name
+        let span = if self.entries[&index].string.tag == Tag::Span {
+            match decoder
+                .decode_format(Some(index), "{=u8}{=u16}")?
+                .as_slice()
+            {
+                [Arg::Uxx(0), Arg::Uxx(id)] => Some(SpanEvent::Enter { id: *id as u16 }),
+                [Arg::Uxx(1), Arg::Uxx(id)] => Some(SpanEvent::Exit {
+                    id: *id as u16,
+                    elapsed: None,
+                }),
+                _ => {
+                    return Err(DecodeError::Malformed(MalformedFrame {
+                        offset: args_offset,
+                        index: Some(index),
+                        format: Some(format.to_owned()),
+                        reason: MalformedReason::InvalidSpanKind,
+                    }))
+                }
+            }
+        } else {
+            None
+        };
+
+        // every field is encoded like a `{=?}` argument
+        let mut fields = vec![];
+        for name in &self.entries[&index].string.fields {
+            let mut value = decoder.decode_format(Some(index), "{=?}")?;
+            fields.push((&**name, value.remove(0)));
+        }
 
-        let args = decoder.decode_format(format)?;
+        if self.crc {
+            let offset = decoder.offset();
+            let computed = crc::crc16(&frame_bytes[..offset]);
+            let received = decoder.bytes.read_u16::<LE>()?;
+            if received != computed {
+                return Err(DecodeError::Malformed(MalformedFrame {
+                    offset,
+                    index: Some(index),
+                    format: Some(format.to_owned()),
+                    reason: MalformedReason::CrcMismatch { received, computed },
+                }));
+            }
+        }
+
+        if self.entries[&index].string.tag == Tag::BuildId {
+            match args.as_slice() {
+                [Arg::Uxx(firmware)] => self.check_build_id(*firmware as u32)?,
+                _ => {
+                    return Err(DecodeError::Malformed(MalformedFrame {
+                        offset: args_offset,
+                        index: Some(index),
+                        format: Some(format.to_owned()),
+                        reason: MalformedReason::InvalidBuildIdFormat,
+                    }))
+                }
+            }
+        }
 
-        let frame = Frame::new(level, index, timestamp_format, timestamp_args, format, args);
+        let mut frame = Frame::new(
+            level,
+            index as u64,
+            timestamp_format,
+            timestamp_args,
+            format,
+            args,
+        );
+        frame.set_sequence_number(sequence_number);
+        frame.set_fields(fields);
+        frame.set_span(span);
 
         let consumed = len - decoder.bytes.len();
         Ok((frame, consumed))
     }
+
+    fn check_build_id(&self, firmware: u32) -> Result<(), DecodeError> {
+        if self.build_id == Some(firmware) {
+            return Ok(());
+        }
+
+        Err(DecodeError::BuildIdMismatch {
+            firmware,
+            table: self.build_id,
+        })
+    }
+
+    /// Finds the start of the next log frame after `decode` has reported `Malformed` data
+    ///
+    /// Returns the number of bytes that must be discarded from the start of `bytes` (at least one
+    /// if `bytes` is not empty). A candidate frame is accepted if it starts with the index of a
+    /// log statement, decodes cleanly and is followed by the index of another log statement. A
+    /// candidate that runs out of data is accepted too; decoding it needs more data.
+    pub fn resync(&self, bytes: &[u8]) -> usize {
+        (1..bytes.len())
+            .find(|&start| self.is_plausible_frame(&bytes[start..]))
+            .unwrap_or(bytes.len())
+    }
+
+    fn is_plausible_frame(&self, bytes: &[u8]) -> bool {
+        /// strings and slices longer than this are considered garbage while resynchronising
+        const MAX_LEN: usize = 4096;
+
+        let is_log_index = |bytes: &[u8]| match bytes {
+            [low, high, ..] => {
+                let index = u16::from_le_bytes([*low, *high]);
+                self.get_with_level(index as usize).is_ok()
+            }
+            // can't tell yet
+            _ => true,
+        };
+
+        if !is_log_index(bytes) {
+            return false;
+        }
+
+        match self.decode_impl(bytes, MAX_LEN) {
+            Ok((_, consumed)) => is_log_index(&bytes[consumed..]),
+            Err(DecodeError::UnexpectedEof) => true,
+            Err(DecodeError::Malformed(_)) => false,
+            // the frame itself is well-formed
+            Err(DecodeError::BuildIdMismatch { .. }) => true,
+        }
+    }
+
+    /// Decodes a single rzCOBS encoded frame (see [`Encoding::Rzcobs`])
+    ///
+    /// * frame: the encoded frame, without the `0x00` delimiter
+    pub fn decode_rzcobs<'t>(&'t self, frame: &[u8]) -> Result<Frame<'t>, DecodeError> {
+        let bytes = rzcobs::decode(frame)?;
+        match self.decode(&bytes) {
+            Ok((frame, _consumed)) => Ok(frame),
+            // the frame is complete so missing data means it's corrupted
+            Err(DecodeError::UnexpectedEof) => {
+                let index = match bytes[..] {
+                    [low, high, ..] => Some(u16::from_le_bytes([low, high]) as usize),
+                    _ => None,
+                };
+                let format = index
+                    .and_then(|index| self._get(index).ok())
+                    .map(|(_, format)| format.to_owned());
+                Err(DecodeError::Malformed(MalformedFrame {
+                    offset: bytes.len(),
+                    index,
+                    format,
+                    reason: MalformedReason::Truncated,
+                }))
+            }
+            Err(e) => Err(e),
+        }
+    }
 }
 
 // NOTE follows `parser::Type`
@@ -229,6 +514,10 @@ enum Arg<'t> {
     FormatSlice {
         elements: Vec<FormatSliceElement<'t>>,
     },
+    /// `Format` values written by a `Formatter` builder, e.g. the entries of `debug_list`
+    FormatSequence {
+        elements: Vec<FormatSliceElement<'t>>,
+    },
     /// Slice or Array of bytes.
     Slice(Vec<u8>),
     /// Char
@@ -251,16 +540,72 @@ pub enum DecodeError {
     /// More data is needed to decode the next frame.
     UnexpectedEof,
 
-    Malformed,
+    /// The data is not a valid log frame
+    Malformed(MalformedFrame),
+
+    /// The build ID sent by the firmware doesn't match the one of the ELF file (or dictionary)
+    ///
+    /// Unlike `Malformed` this error is fatal: every other frame of the stream will be decoded
+    /// with the wrong table, so tools should stop decoding instead of skipping the frame.
+    BuildIdMismatch { firmware: u32, table: Option<u32> },
+}
+
+/// Where and why a log frame could not be decoded
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MalformedFrame {
+    /// Offset of the offending data from the start of the frame
+    ///
+    /// With the rzCOBS encoding this is an offset into the decoded frame, except for
+    /// [`MalformedReason::InvalidRzcobs`] errors.
+    pub offset: usize,
+    /// Table index of the format string that was being decoded, if known
+    pub index: Option<usize>,
+    /// The format string that was being decoded, if known
+    pub format: Option<String>,
+    pub reason: MalformedReason,
+}
+
+/// Why a log frame could not be decoded
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum MalformedReason {
+    /// The table has no string with this index
+    UnknownIndex(usize),
+    /// The frame header refers to a string that is not a log statement
+    NotALogStatement(usize),
+    /// An interned string or `Format` argument refers to a log statement
+    UnexpectedLogStatement(usize),
+    /// A `bool` argument is neither `0` nor `1`
+    InvalidBool(u8),
+    /// An element of a sequence (see `Formatter::debug_list`) is preceded by neither `0` nor `1`
+    InvalidSequenceMarker(u8),
+    /// A `char` argument is not a Unicode scalar value
+    InvalidChar(u32),
+    /// A string argument is not valid UTF-8
+    InvalidUtf8,
+    /// The discriminant of an enum is out of range
+    UnknownDiscriminant { discriminant: u64, variants: usize },
+    /// The length of a string or slice is above the limit used while resynchronising
+    LengthTooLarge(usize),
+    /// The format string could not be parsed
+    InvalidFormatString(String),
+    /// The frame is not valid rzCOBS
+    InvalidRzcobs,
+    /// The (rzCOBS) frame ended before all arguments were decoded
+    Truncated,
+    /// The CRC at the end of the frame doesn't match its data; the frame was corrupted
+    CrcMismatch { received: u16, computed: u16 },
+    /// A span frame is neither an enter nor an exit frame
+    InvalidSpanKind,
+    /// The build ID string doesn't have a single integer parameter
+    InvalidBuildIdFormat,
 }
 
 impl From<io::Error> for DecodeError {
     fn from(e: io::Error) -> Self {
-        if e.kind() == io::ErrorKind::UnexpectedEof {
-            DecodeError::UnexpectedEof
-        } else {
-            DecodeError::Malformed
-        }
+        // reading from a byte slice only fails when the data runs out
+        debug_assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        DecodeError::UnexpectedEof
     }
 }
 
@@ -268,11 +613,96 @@ impl fmt::Display for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             DecodeError::UnexpectedEof => f.write_str("unexpected end of stream"),
-            DecodeError::Malformed => f.write_str("malformed data"),
+            DecodeError::Malformed(malformed) => malformed.fmt(f),
+            DecodeError::BuildIdMismatch {
+                firmware,
+                table: Some(table),
+            } => write!(
+                f,
+                "the firmware has build ID {:#010x} but the ELF file has build ID {:#010x}; \
+                 the logs are not being decoded with the ELF file the firmware was built from",
+                firmware, table
+            ),
+            DecodeError::BuildIdMismatch {
+                firmware,
+                table: None,
+            } => write!(
+                f,
+                "the firmware has build ID {:#010x} but the ELF file has no build ID; \
+                 the logs are not being decoded with the ELF file the firmware was built from",
+                firmware
+            ),
+        }
+    }
+}
+
+impl fmt::Display for MalformedFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed data at byte {}: {}", self.offset, self.reason)?;
+        match (self.index, &self.format) {
+            (Some(index), Some(format)) => write!(f, " (decoding string #{}: {:?})", index, format),
+            (None, Some(format)) => write!(f, " (decoding {:?})", format),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for MalformedReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MalformedReason::UnknownIndex(index) => write!(
+                f,
+                "unknown string index {}; was the firmware built from this ELF file?",
+                index
+            ),
+            MalformedReason::NotALogStatement(index) => write!(
+                f,
+                "the frame header refers to string #{} which is not a log statement",
+                index
+            ),
+            MalformedReason::UnexpectedLogStatement(index) => write!(
+                f,
+                "string #{} is a log statement; expected an interned string or a format string",
+                index
+            ),
+            MalformedReason::InvalidBool(value) => write!(f, "invalid `bool` value {:#04x}", value),
+            MalformedReason::InvalidSequenceMarker(value) => {
+                write!(f, "invalid sequence element marker {:#04x}", value)
+            }
+            MalformedReason::InvalidChar(value) => write!(f, "invalid `char` value {:#x}", value),
+            MalformedReason::InvalidUtf8 => f.write_str("string argument is not valid UTF-8"),
+            MalformedReason::UnknownDiscriminant {
+                discriminant,
+                variants,
+            } => write!(
+                f,
+                "enum discriminant {} is out of range; the enum has {} variants",
+                discriminant, variants
+            ),
+            MalformedReason::LengthTooLarge(len) => write!(f, "implausible length {}", len),
+            MalformedReason::InvalidFormatString(e) => write!(f, "invalid format string: {}", e),
+            MalformedReason::InvalidRzcobs => f.write_str("invalid rzCOBS encoding"),
+            MalformedReason::Truncated => {
+                f.write_str("the frame ended before all arguments were decoded")
+            }
+            MalformedReason::InvalidSpanKind => {
+                f.write_str("the span frame is neither an enter nor an exit frame")
+            }
+            MalformedReason::InvalidBuildIdFormat => f.write_str(
+                "the build ID string doesn't have a single integer parameter; \
+                 was the firmware built with a different version of defmt?",
+            ),
+            MalformedReason::CrcMismatch { received, computed } => write!(
+                f,
+                "CRC mismatch: the frame ends with CRC {:#06x} but its data has CRC {:#06x}",
+                received, computed
+            ),
         }
     }
 }
 
+impl Error for MalformedFrame {}
+
 impl Error for DecodeError {}
 
 #[cfg(test)]
@@ -293,6 +723,10 @@ mod tests {
 
         let table = Table {
             entries,
+            encoding: Encoding::Raw,
+            build_id: None,
+            sequence_numbers: false,
+            crc: false,
             timestamp: Some(TableEntry::new_without_symbol(
                 Tag::Timestamp,
                 "{=u8:µs}".to_owned(),
@@ -320,6 +754,10 @@ mod tests {
 
         let table = Table {
             entries,
+            encoding: Encoding::Raw,
+            build_id: None,
+            sequence_numbers: false,
+            crc: false,
             timestamp: None,
         };
 
@@ -357,6 +795,426 @@ mod tests {
         // TODO Format ({:?})
     }
 
+    #[test]
+    fn malformed() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Info, "{=bool} {=char} {=str}".to_owned()),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Info, "{=u8} {=?}".to_owned()),
+        );
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Prim, "None|Some({=u8})".to_owned()),
+        );
+        let table = Table::new(entries);
+
+        let malformed = |offset, index, format: Option<&str>, reason| {
+            Err(DecodeError::Malformed(MalformedFrame {
+                offset,
+                index,
+                format: format.map(str::to_owned),
+                reason,
+            }))
+        };
+
+        assert_eq!(
+            table.decode(&[3, 0]),
+            malformed(0, Some(3), None, MalformedReason::UnknownIndex(3))
+        );
+        assert_eq!(
+            table.decode(&[2, 0]),
+            malformed(0, Some(2), None, MalformedReason::NotALogStatement(2))
+        );
+
+        let format = Some("{=bool} {=char} {=str}");
+        assert_eq!(
+            table.decode(&[0, 0, 2]),
+            malformed(2, Some(0), format, MalformedReason::InvalidBool(2))
+        );
+        assert_eq!(
+            table.decode(&[0, 0, 1, 0, 0xd8, 0, 0]),
+            malformed(3, Some(0), format, MalformedReason::InvalidChar(0xd800))
+        );
+        assert_eq!(
+            table.decode(&[0, 0, 1, b'x', 0, 0, 0, 1, 0, 0, 0, 0xff]),
+            malformed(7, Some(0), format, MalformedReason::InvalidUtf8)
+        );
+
+        // errors in `Format` arguments refer to their format string
+        assert_eq!(
+            table.decode(&[1, 0, 42, 2, 0, 2]),
+            malformed(
+                5,
+                Some(2),
+                Some("None|Some({=u8})"),
+                MalformedReason::UnknownDiscriminant {
+                    discriminant: 2,
+                    variants: 2
+                }
+            )
+        );
+        assert_eq!(
+            table.decode(&[1, 0, 42, 1, 0]),
+            malformed(
+                3,
+                Some(1),
+                Some("{=u8} {=?}"),
+                MalformedReason::UnexpectedLogStatement(1)
+            )
+        );
+    }
+
+    #[test]
+    fn malformed_display() {
+        let error = DecodeError::Malformed(MalformedFrame {
+            offset: 2,
+            index: Some(0),
+            format: Some("x={=bool}".to_owned()),
+            reason: MalformedReason::InvalidBool(2),
+        });
+        assert_eq!(
+            error.to_string(),
+            "malformed data at byte 2: invalid `bool` value 0x02 (decoding string #0: \"x={=bool}\")"
+        );
+    }
+
+    #[test]
+    fn build_id() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::BuildId, "defmt build ID: {=u32:#010x}".to_owned()),
+        );
+        let mut table = Table::new(entries);
+        table.set_timestamp_entry(TableEntry::new_without_symbol(
+            Tag::Timestamp,
+            "{=u8}".to_owned(),
+        ));
+        let bytes = [0, 0, 7, 0x78, 0x56, 0x34, 0x12];
+
+        // built without the `build-id` feature
+        assert_eq!(
+            table.decode(&bytes).unwrap_err(),
+            DecodeError::BuildIdMismatch {
+                firmware: 0x1234_5678,
+                table: None
+            }
+        );
+
+        table.set_build_id(0x1234_5679);
+        assert_eq!(
+            table.decode(&bytes).unwrap_err(),
+            DecodeError::BuildIdMismatch {
+                firmware: 0x1234_5678,
+                table: Some(0x1234_5679)
+            }
+        );
+
+        table.set_build_id(0x1234_5678);
+        let (frame, _) = table.decode(&bytes).unwrap();
+        assert_eq!(
+            frame.display_message().to_string(),
+            "defmt build ID: 0x12345678"
+        );
+    }
+
+    #[test]
+    fn build_id_invalid_format() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::BuildId, "defmt build ID: {=u8}{=u8}".to_owned()),
+        );
+        let mut table = Table::new(entries);
+        table.set_timestamp_entry(TableEntry::new_without_symbol(
+            Tag::Timestamp,
+            "{=u8}".to_owned(),
+        ));
+        table.set_build_id(0x1234_5678);
+
+        assert_eq!(
+            table.decode(&[0, 0, 7, 0x78, 0x56]).unwrap_err(),
+            DecodeError::Malformed(MalformedFrame {
+                offset: 3,
+                index: Some(0),
+                format: Some("defmt build ID: {=u8}{=u8}".to_owned()),
+                reason: MalformedReason::InvalidBuildIdFormat,
+            })
+        );
+    }
+
+    #[test]
+    fn crc() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Info, "x={=u8}".to_owned()),
+        );
+        let mut table = Table::new(entries);
+        table.set_crc(true);
+
+        let (frame, consumed) = table.decode(&[0, 0, 42, 0xb4, 0x49]).unwrap();
+        assert_eq!(frame.display_message().to_string(), "x=42");
+        assert_eq!(consumed, 5);
+
+        // bit flip in the argument
+        assert_eq!(
+            table.decode(&[0, 0, 43, 0xb4, 0x49]),
+            Err(DecodeError::Malformed(MalformedFrame {
+                offset: 3,
+                index: Some(0),
+                format: Some("x={=u8}".to_owned()),
+                reason: MalformedReason::CrcMismatch {
+                    received: 0x49b4,
+                    computed: crc::crc16(&[0, 0, 43]),
+                },
+            }))
+        );
+
+        // the CRC is part of the frame
+        assert_eq!(
+            table.decode(&[0, 0, 42, 0xb4]),
+            Err(DecodeError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new(
+                StringEntry::new(Tag::Info, "connected after {=u8} tries".to_owned())
+                    .with_fields(vec!["peer".to_owned(), "rssi".to_owned()]),
+                "<unknown>".to_owned(),
+            ),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Prim, "{=u8}".to_owned()),
+        );
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Prim, "{=i8}".to_owned()),
+        );
+        let table = Table::new(entries);
+
+        let bytes = [
+            0, 0, // index
+            3, // argument
+            1, 0, 7, // `peer` field: index of `{=u8}`, value
+            2, 0, 0xc4, // `rssi` field: index of `{=i8}`, value
+        ];
+        let (frame, consumed) = table.decode(&bytes).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(
+            frame.display_message().to_string(),
+            "connected after 3 tries peer=7 rssi=-60"
+        );
+        assert_eq!(frame.args().len(), 1);
+        assert_eq!(
+            frame.fields().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["peer", "rssi"]
+        );
+        assert!(matches!(
+            frame.field("rssi"),
+            Some(Value::Format(value)) if value.args().next() == Some(Value::Signed(-60))
+        ));
+        assert_eq!(frame.field("ssid"), None);
+    }
+
+    #[test]
+    fn span() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Span, "flash".to_owned()),
+        );
+        let table = Table::new(entries);
+
+        let bytes = [
+            0, 0, // index
+            0, // enter
+            3, 0, // ID
+        ];
+        let (frame, consumed) = table.decode(&bytes).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(frame.level(), Level::Trace);
+        assert_eq!(frame.span(), Some(SpanEvent::Enter { id: 3 }));
+        assert_eq!(frame.display_message().to_string(), "→ flash");
+
+        let bytes = [0, 0, 1, 3, 0];
+        let (frame, _) = table.decode(&bytes).unwrap();
+        assert_eq!(
+            frame.span(),
+            Some(SpanEvent::Exit {
+                id: 3,
+                elapsed: None
+            })
+        );
+        assert_eq!(frame.display_message().to_string(), "← flash");
+
+        let bytes = [0, 0, 2, 3, 0];
+        assert!(matches!(
+            table.decode(&bytes),
+            Err(DecodeError::Malformed(MalformedFrame {
+                reason: MalformedReason::InvalidSpanKind,
+                ..
+            }))
+        ));
+    }
+
+    fn builder_table() -> Table {
+        let formats = vec![
+            (Tag::Info, "{=?}"),
+            (Tag::Prim, "{=istr} {{ {=__internal_FormatSequence} }}"),
+            (Tag::Prim, "{=istr}: {=?}"),
+            (Tag::Prim, "{=u8}"),
+            (Tag::Str, "Header"),
+            (Tag::Str, "src"),
+            (Tag::Str, "dst"),
+            (Tag::Prim, "[{=__internal_FormatSequence}]"),
+            (Tag::Prim, "{{{=__internal_FormatSequence}}}"),
+            (Tag::Prim, "{=?}: {=?}"),
+        ];
+        let entries = formats
+            .into_iter()
+            .enumerate()
+            .map(|(index, (tag, format))| {
+                (
+                    index,
+                    TableEntry::new_without_symbol(tag, format.to_owned()),
+                )
+            })
+            .collect();
+        Table::new(entries)
+    }
+
+    #[test]
+    fn format_sequence() {
+        let table = builder_table();
+
+        let bytes = [
+            0, 0, // index
+            1, 0, // `debug_struct`
+            4, 0, // name
+            1, 2, 0, 5, 0, 3, 0, 1, // element `src: 1`
+            1, 2, 0, 6, 0, 3, 0, 2, // element `dst: 2`
+            0, // end
+        ];
+        let (frame, consumed) = table.decode(&bytes).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(
+            frame.display_message().to_string(),
+            "Header { src: 1, dst: 2 }"
+        );
+
+        let bytes = [
+            0, 0, // index
+            7, 0, // `debug_list`
+            1, 3, 0, 1, // element `1`
+            1, 3, 0, 2, // element `2`
+            0, // end
+        ];
+        let (frame, _) = table.decode(&bytes).unwrap();
+        assert_eq!(frame.display_message().to_string(), "[1, 2]");
+
+        let bytes = [
+            0, 0, // index
+            8, 0, // `debug_map`
+            1, 9, 0, 3, 0, 1, 3, 0, 2, // element `1: 2`
+            0, // end
+        ];
+        let (frame, _) = table.decode(&bytes).unwrap();
+        assert_eq!(frame.display_message().to_string(), "{1: 2}");
+
+        let bytes = [0, 0, 7, 0, 0];
+        let (frame, _) = table.decode(&bytes).unwrap();
+        assert_eq!(frame.display_message().to_string(), "[]");
+
+        let bytes = [0, 0, 7, 0, 2];
+        assert!(matches!(
+            table.decode(&bytes),
+            Err(DecodeError::Malformed(MalformedFrame {
+                reason: MalformedReason::InvalidSequenceMarker(2),
+                ..
+            }))
+        ));
+    }
+
+    fn resync_table() -> Table {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Info, "Hello, world!".to_owned()),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Debug, "The answer is {=u8}!".to_owned()),
+        );
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Info, "{=str}".to_owned()),
+        );
+        entries.insert(3, TableEntry::new_without_symbol(Tag::Str, "x".to_owned()));
+        Table::new(entries)
+    }
+
+    #[test]
+    fn resync() {
+        let table = resync_table();
+
+        let bytes = [
+            0xff, 0xff, // garbage
+            1, 0, 42, // frame
+            0, 0, // frame
+        ];
+        assert!(matches!(
+            table.decode(&bytes),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert_eq!(table.resync(&bytes), 2);
+
+        // `[0, 0]` at offset 1 decodes but isn't followed by a log frame
+        let bytes = [0xee, 0, 0, 0xee, 0xee, 1, 0, 42];
+        assert_eq!(table.resync(&bytes), 5);
+
+        // `[3, 0]` is not the index of a log statement
+        let bytes = [0xee, 3, 0, 0, 0];
+        assert_eq!(table.resync(&bytes), 2);
+    }
+
+    #[test]
+    fn resync_needs_more_data() {
+        let table = resync_table();
+
+        // the frame at offset 1 is incomplete
+        let bytes = [0xee, 1, 0];
+        assert_eq!(table.resync(&bytes), 1);
+
+        // the last byte could be the start of an index
+        let bytes = [0xee, 0xee, 0xee];
+        assert_eq!(table.resync(&bytes), 2);
+
+        assert_eq!(table.resync(&[0xee]), 1);
+    }
+
+    #[test]
+    fn resync_rejects_implausible_lengths() {
+        let table = resync_table();
+
+        let bytes = [
+            0xee, // garbage
+            2, 0, 0xff, 0xff, 0xff, 0x7f, // string with a length of 2 GiB
+            0, 0, // frame
+        ];
+        assert_eq!(table.resync(&bytes), 7);
+    }
+
     #[test]
     fn all_integers() {
         const FMT: &str =
@@ -366,6 +1224,10 @@ mod tests {
 
         let table = Table {
             entries,
+            encoding: Encoding::Raw,
+            build_id: None,
+            sequence_numbers: false,
+            crc: false,
             timestamp: None,
         };
 
@@ -431,6 +1293,10 @@ mod tests {
 
         let table = Table {
             entries,
+            encoding: Encoding::Raw,
+            build_id: None,
+            sequence_numbers: false,
+            crc: false,
             timestamp: None,
         };
         let bytes = [
@@ -489,6 +1355,10 @@ mod tests {
 
         let table = Table {
             entries,
+            encoding: Encoding::Raw,
+            build_id: None,
+            sequence_numbers: false,
+            crc: false,
             timestamp: None,
         };
 
@@ -531,6 +1401,10 @@ mod tests {
 
         let table = Table {
             entries,
+            encoding: Encoding::Raw,
+            build_id: None,
+            sequence_numbers: false,
+            crc: false,
             timestamp: Some(TableEntry::new_without_symbol(
                 Tag::Timestamp,
                 "{=u8:µs}".to_owned(),
@@ -775,6 +1649,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn named_args() {
+        let bytes = [
+            0, 0,  // index
+            2,  // timestamp
+            42, // positional argument
+            1, 0, // len, after the positional arguments
+        ];
+
+        decode_and_expect(
+            "read {len=u16} of {=u8} bytes ({len=u16:#x})",
+            &bytes,
+            "0.000002 INFO read 1 of 42 bytes (0x1)",
+        );
+    }
+
     #[test]
     fn string_hello_world() {
         let bytes = [
@@ -837,6 +1727,10 @@ mod tests {
 
         let table = Table {
             entries,
+            encoding: Encoding::Raw,
+            build_id: None,
+            sequence_numbers: false,
+            crc: false,
             timestamp: Some(TableEntry::new_without_symbol(
                 Tag::Timestamp,
                 "{=u8:µs}".to_owned(),
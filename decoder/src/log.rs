@@ -44,7 +44,8 @@ pub fn log_defmt(
 
     log::logger().log(
         &Record::builder()
-            .args(format_args!("{}", display))
+            // nest the frames logged inside of spans
+            .args(format_args!("{:1$}{2}", "", frame.depth() * 2, display))
             .level(level)
             .target(&target)
             .module_path(module_path)
@@ -0,0 +1,134 @@
+//! Decoder for the rzCOBS frame encoding; see `defmt/src/encoding/rzcobs.rs` for the encoder
+
+use crate::{DecodeError, MalformedFrame, MalformedReason};
+
+/// Decodes an rzCOBS encoded frame
+///
+/// `encoded` must not include the `0x00` frame delimiter. The output may include trailing zeros
+/// that are not part of the original frame.
+pub(crate) fn decode(encoded: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    // the encoding is decoded back-to-front
+    let mut bytes = encoded.iter().rev().copied();
+    // running out of data means the front of the frame is missing
+    let mut next = || bytes.next().ok_or_else(|| invalid(0));
+
+    while let Ok(marker) = next() {
+        match marker {
+            0x00 => return Err(invalid(bytes.len())),
+            0x01..=0x7f => {
+                for i in (0..7).rev() {
+                    if marker & (1 << i) == 0 {
+                        decoded.push(next()?);
+                    } else {
+                        decoded.push(0);
+                    }
+                }
+            }
+            0x80..=0xfe => {
+                decoded.push(0);
+                for _ in 0..(marker & 0x7f) + 7 {
+                    decoded.push(next()?);
+                }
+            }
+            0xff => {
+                for _ in 0..134 {
+                    decoded.push(next()?);
+                }
+            }
+        }
+    }
+
+    decoded.reverse();
+    Ok(decoded)
+}
+
+fn invalid(offset: usize) -> DecodeError {
+    DecodeError::Malformed(MalformedFrame {
+        offset,
+        index: None,
+        format: None,
+        reason: MalformedReason::InvalidRzcobs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_runs() {
+        assert_eq!(decode(&[]), Ok(vec![]));
+        assert_eq!(decode(&[0x7f]), Ok(vec![0; 7]));
+        assert_eq!(decode(&[0x11, 0x7e]), Ok(vec![0x11, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(
+            decode(&[0x11, 0x22, 0x7a]),
+            Ok(vec![0x11, 0x00, 0x22, 0, 0, 0, 0])
+        );
+        assert_eq!(
+            decode(&[1, 2, 3, 4, 5, 6, 0b10, 7, 0x7e]),
+            Ok(vec![1, 0, 2, 3, 4, 5, 6, 7, 0, 0, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn long_runs() {
+        let data = [0xaa; 10];
+        assert_eq!(
+            decode(&[&data[..], &[0x83]].concat()),
+            Ok([&data[..], &[0]].concat())
+        );
+
+        let data = [0xaa; 134];
+        assert_eq!(decode(&[&data[..], &[0xff]].concat()), Ok(data.to_vec()));
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(decode(&[0x7e]), Err(invalid(0)));
+        assert_eq!(decode(&[0xaa, 0x83]), Err(invalid(0)));
+    }
+
+    #[test]
+    fn decode_frame() {
+        use crate::{Table, TableEntry, Tag};
+        use std::collections::BTreeMap;
+
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Info, "x={=u16}".to_owned()),
+        );
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Info, "y={=u128}".to_owned()),
+        );
+        let table = Table::new(entries);
+
+        // index = 1, x = 0x100 -> [0x01, 0x00, 0x00, 0x01]
+        let frame = table.decode_rzcobs(&[0x01, 0x01, 0x76]).unwrap();
+        assert_eq!(frame.display_message().to_string(), "x=256");
+
+        // unknown index
+        assert_eq!(
+            table.decode_rzcobs(&[0x05, 0x7e]),
+            Err(DecodeError::Malformed(MalformedFrame {
+                offset: 0,
+                index: Some(5),
+                format: None,
+                reason: MalformedReason::UnknownIndex(5),
+            }))
+        );
+
+        // the frame ends before the argument
+        assert_eq!(
+            table.decode_rzcobs(&[0x02, 0x7e]),
+            Err(DecodeError::Malformed(MalformedFrame {
+                offset: 7,
+                index: Some(2),
+                format: Some("y={=u128}".to_owned()),
+                reason: MalformedReason::Truncated,
+            }))
+        );
+    }
+}
@@ -0,0 +1,474 @@
+use std::{collections::BTreeMap, mem};
+
+use crate::{DecodeError, Encoding, Frame, SpanEvent, Table};
+
+/// Spans nested deeper than this are assumed to be left over from lost exit frames
+const MAX_SPAN_DEPTH: usize = 64;
+
+/// Decodes log frames from a stream of data that arrives in arbitrary chunks
+///
+/// ``` ignore
+/// let mut stream = StreamDecoder::new(&table);
+/// loop {
+///     let n = reader.read(&mut buf)?;
+///     stream.received(&buf[..n]);
+///     loop {
+///         match stream.decode() {
+///             Ok(frame) => println!("{}", frame.display(true)),
+///             Err(DecodeError::UnexpectedEof) => break,
+///             Err(DecodeError::Malformed(e)) => eprintln!("skipping malformed defmt data: {}", e),
+///             Err(e @ DecodeError::BuildIdMismatch { .. }) => return Err(e.into()),
+///         }
+///     }
+/// }
+/// ```
+pub struct StreamDecoder<'t> {
+    table: &'t Table,
+    buf: Vec<u8>,
+    /// Start of the data in `buf` that has not been decoded yet
+    pos: usize,
+    /// Sequence number of the last decoded frame
+    last_sequence_number: Option<u8>,
+    /// Whether the stream may still start with the tail of a frame
+    mid_stream: bool,
+    /// The spans that have been entered but not exited: their timestamp ticks and depth
+    open_spans: BTreeMap<u16, (Option<u128>, usize)>,
+}
+
+impl<'t> StreamDecoder<'t> {
+    /// Creates a decoder for the stream of data logged by the firmware `table` belongs to
+    pub fn new(table: &'t Table) -> Self {
+        Self {
+            table,
+            buf: Vec::new(),
+            pos: 0,
+            last_sequence_number: None,
+            mid_stream: false,
+            open_spans: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a decoder for a stream of data that may start in the middle of a frame
+    ///
+    /// This is the case for data that has been read out of a ring buffer that overwrites its
+    /// oldest data. If the first frame of the stream can't be decoded it is silently skipped
+    /// instead of being reported as `Malformed`. A truncated frame may still happen to decode
+    /// (to garbage); enabling the firmware's `crc` feature rules that out.
+    pub fn new_mid_stream(table: &'t Table) -> Self {
+        Self {
+            mid_stream: true,
+            ..Self::new(table)
+        }
+    }
+
+    /// Appends `data` to the stream
+    pub fn received(&mut self, data: &[u8]) {
+        // discard the decoded data once per chunk rather than once per frame
+        if self.pos != 0 {
+            self.buf.drain(..mem::replace(&mut self.pos, 0));
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Decodes the next log frame
+    ///
+    /// Returns `UnexpectedEof` when more data must be `received` to decode the next frame. When
+    /// `Malformed` is returned the offending data has already been skipped and decoding can
+    /// continue with the next call. `BuildIdMismatch` is reported even for the first frame of a
+    /// stream created with [`StreamDecoder::new_mid_stream`]; decoding should stop there.
+    ///
+    /// If the firmware sends sequence numbers, [`Frame::lost_frames`] reports the frames that are
+    /// missing from the stream, including malformed ones. Note that a reset of the firmware looks
+    /// like lost frames too.
+    ///
+    /// The exit frame of a span is paired with its enter frame to compute
+    /// [`Frame::depth`] and the time spent in the span. The open spans are forgotten when frames
+    /// are lost, when a span ID is reused and when the spans entered within a span are still open
+    /// when it exits, as their exit frames must have been lost (or the firmware was reset).
+    pub fn decode(&mut self) -> Result<Frame<'t>, DecodeError> {
+        let mut frame = loop {
+            match self.decode_frame() {
+                Err(DecodeError::Malformed(_)) if self.mid_stream => {
+                    // the partial first frame has been skipped; the next one starts at a frame
+                    // boundary
+                    self.mid_stream = false;
+                }
+                result => {
+                    if result.is_ok() {
+                        self.mid_stream = false;
+                    }
+                    break result?;
+                }
+            }
+        };
+        if let Some(sequence_number) = frame.sequence_number() {
+            if let Some(last) = self.last_sequence_number {
+                let lost = sequence_number.wrapping_sub(last).wrapping_sub(1);
+                frame.set_lost_frames(usize::from(lost));
+            }
+            self.last_sequence_number = Some(sequence_number);
+        }
+        self.track_spans(&mut frame);
+        Ok(frame)
+    }
+
+    fn track_spans(&mut self, frame: &mut Frame<'t>) {
+        // the lost frames may include exit frames
+        if frame.lost_frames() != 0 || self.open_spans.len() > MAX_SPAN_DEPTH {
+            self.open_spans.clear();
+        }
+
+        match frame.span() {
+            Some(SpanEvent::Enter { id }) => {
+                // the span that had this ID has exited, and so have the spans entered within it
+                if let Some(&(_, depth)) = self.open_spans.get(&id) {
+                    self.open_spans.retain(|_, (_, open)| *open < depth);
+                }
+                let depth = self.open_spans.len();
+                self.open_spans.insert(id, (frame.timestamp_ticks(), depth));
+                frame.set_depth(depth);
+            }
+            Some(SpanEvent::Exit { id, .. }) => match self.open_spans.remove(&id) {
+                Some((entered, depth)) => {
+                    // spans entered within this one have exited too
+                    self.open_spans.retain(|_, (_, open)| *open < depth);
+                    let elapsed = entered
+                        .zip(frame.timestamp_ticks())
+                        .and_then(|(entered, exited)| exited.checked_sub(entered));
+                    frame.set_span(Some(SpanEvent::Exit { id, elapsed }));
+                    frame.set_depth(depth);
+                }
+                // the enter frame was lost or logged before we attached
+                None => frame.set_depth(self.open_spans.len().saturating_sub(1)),
+            },
+            None => frame.set_depth(self.open_spans.len()),
+        }
+    }
+
+    fn decode_frame(&mut self) -> Result<Frame<'t>, DecodeError> {
+        let bytes = &self.buf[self.pos..];
+        match self.table.encoding() {
+            Encoding::Raw => match self.table.decode(bytes) {
+                Ok((frame, consumed)) => {
+                    self.pos += consumed;
+                    Ok(frame)
+                }
+                Err(DecodeError::UnexpectedEof) => Err(DecodeError::UnexpectedEof),
+                Err(e) => {
+                    // e.g. we attached mid-stream or the target dropped data
+                    self.pos += self.table.resync(bytes);
+                    Err(e)
+                }
+            },
+            Encoding::Rzcobs => {
+                let end = bytes
+                    .iter()
+                    .position(|byte| *byte == 0)
+                    .ok_or(DecodeError::UnexpectedEof)?;
+                // the frame delimiter lets us recover from malformed frames; skip the frame
+                // either way
+                let frame = self.table.decode_rzcobs(&bytes[..end]);
+                self.pos += end + 1;
+                frame
+            }
+        }
+    }
+
+    /// Returns the data that has been received but not decoded yet
+    pub fn remaining(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{MalformedReason, TableEntry, Tag};
+
+    fn table(encoding: Encoding) -> Table {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Info, "ping".to_owned()),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Info, "x={=u16}".to_owned()),
+        );
+        let mut table = Table::new(entries);
+        table.set_encoding(encoding);
+        table
+    }
+
+    fn decode_all(stream: &mut StreamDecoder) -> Vec<Result<String, MalformedReason>> {
+        let mut frames = vec![];
+        loop {
+            match stream.decode() {
+                Ok(frame) => frames.push(Ok(frame.display_message().to_string())),
+                Err(DecodeError::UnexpectedEof) => return frames,
+                Err(DecodeError::Malformed(e)) => frames.push(Err(e.reason)),
+                Err(e @ DecodeError::BuildIdMismatch { .. }) => panic!("{}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn raw_chunks() {
+        let table = table(Encoding::Raw);
+        let mut stream = StreamDecoder::new(&table);
+
+        stream.received(&[0]);
+        assert!(decode_all(&mut stream).is_empty());
+        stream.received(&[0, 1, 0, 0]);
+        assert_eq!(decode_all(&mut stream), [Ok("ping".to_owned())]);
+        assert_eq!(stream.remaining(), [1, 0, 0]);
+        stream.received(&[1, 0, 0, 1]);
+        assert_eq!(
+            decode_all(&mut stream),
+            [Ok("x=256".to_owned()), Ok("ping".to_owned())]
+        );
+        assert_eq!(stream.remaining(), [1]);
+    }
+
+    #[test]
+    fn raw_malformed() {
+        let table = table(Encoding::Raw);
+        let mut stream = StreamDecoder::new(&table);
+
+        stream.received(&[0xff, 0xff, 1, 0, 42, 0, 0, 0]);
+        assert_eq!(
+            decode_all(&mut stream),
+            [
+                Err(MalformedReason::UnknownIndex(0xffff)),
+                Ok("x=42".to_owned()),
+                Ok("ping".to_owned())
+            ]
+        );
+        assert!(stream.remaining().is_empty());
+    }
+
+    #[test]
+    fn lost_frames() {
+        let mut table = table(Encoding::Raw);
+        table.set_sequence_numbers(true);
+        let mut stream = StreamDecoder::new(&table);
+
+        let lost_frames = |stream: &mut StreamDecoder| {
+            let mut lost = vec![];
+            while let Ok(frame) = stream.decode() {
+                lost.push((frame.sequence_number(), frame.lost_frames()));
+            }
+            lost
+        };
+
+        // sequence numbers 254, 255, 0, then 3 (1 and 2 lost)
+        stream.received(&[0, 0, 254, 0, 0, 255, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(
+            lost_frames(&mut stream),
+            [(Some(254), 0), (Some(255), 0), (Some(0), 0), (Some(3), 2)]
+        );
+    }
+
+    #[test]
+    fn rzcobs_chunks() {
+        let table = table(Encoding::Rzcobs);
+        let mut stream = StreamDecoder::new(&table);
+
+        // index = 1, x = 0x100
+        stream.received(&[0x01, 0x01]);
+        assert!(decode_all(&mut stream).is_empty());
+        // the second frame has an unknown index
+        stream.received(&[0x76, 0x00, 0x05, 0x7e, 0x00, 0x7f]);
+        assert_eq!(
+            decode_all(&mut stream),
+            [
+                Ok("x=256".to_owned()),
+                Err(MalformedReason::UnknownIndex(5))
+            ]
+        );
+        stream.received(&[0x00]);
+        assert_eq!(decode_all(&mut stream), [Ok("ping".to_owned())]);
+        assert!(stream.remaining().is_empty());
+    }
+
+    #[test]
+    fn rzcobs_mid_stream() {
+        let table = table(Encoding::Rzcobs);
+
+        // tail of a frame with an unknown index, then `x=256`
+        let mut stream = StreamDecoder::new_mid_stream(&table);
+        stream.received(&[0x05, 0x7e, 0x00, 0x01, 0x01, 0x76, 0x00]);
+        assert_eq!(decode_all(&mut stream), [Ok("x=256".to_owned())]);
+
+        // an intact first frame is not skipped
+        let mut stream = StreamDecoder::new_mid_stream(&table);
+        stream.received(&[0x01, 0x01, 0x76, 0x00, 0x05, 0x7e, 0x00]);
+        assert_eq!(
+            decode_all(&mut stream),
+            [
+                Ok("x=256".to_owned()),
+                Err(MalformedReason::UnknownIndex(5))
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_mid_stream() {
+        let table = table(Encoding::Raw);
+        let mut stream = StreamDecoder::new_mid_stream(&table);
+
+        // tail of a frame, then `x=42` and `ping`
+        stream.received(&[0xff, 0xff, 1, 0, 42, 0, 0, 0]);
+        assert_eq!(
+            decode_all(&mut stream),
+            [Ok("x=42".to_owned()), Ok("ping".to_owned())]
+        );
+    }
+
+    #[test]
+    fn build_id_mismatch_mid_stream() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::BuildId, "defmt build ID: {=u32:#010x}".to_owned()),
+        );
+        let mut table = Table::new(entries);
+        table.set_build_id(0x1234_5679);
+
+        // the mismatch is not mistaken for the tail of a frame
+        let mut stream = StreamDecoder::new_mid_stream(&table);
+        stream.received(&[0, 0, 0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(
+            stream.decode().unwrap_err(),
+            DecodeError::BuildIdMismatch {
+                firmware: 0x1234_5678,
+                table: Some(0x1234_5679)
+            }
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Info, "ping".to_owned()),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Span, "outer".to_owned()),
+        );
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Span, "inner".to_owned()),
+        );
+        let mut table = Table::new(entries);
+        table.set_timestamp_entry(TableEntry::new_without_symbol(
+            Tag::Timestamp,
+            "{=u8:µs}".to_owned(),
+        ));
+        let mut stream = StreamDecoder::new(&table);
+
+        // [index, timestamp, kind, ID]
+        stream.received(&[
+            1, 0, 10, 0, 0, 0, // enter `outer`
+            2, 0, 20, 0, 1, 0, // enter `inner`
+            0, 0, 25, // ping
+            2, 0, 30, 1, 1, 0, // exit `inner`
+            1, 0, 70, 1, 0, 0, // exit `outer`
+            2, 0, 80, 1, 7, 0, // exit of a span that was entered before we attached
+        ]);
+        let mut frames = vec![];
+        while let Ok(frame) = stream.decode() {
+            frames.push((frame.depth(), frame.display_message().to_string()));
+        }
+        assert_eq!(
+            frames,
+            [
+                (0, "→ outer".to_owned()),
+                (1, "→ inner".to_owned()),
+                (2, "ping".to_owned()),
+                (1, "← inner (0.000010)".to_owned()),
+                (0, "← outer (0.000060)".to_owned()),
+                (0, "← inner".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_lost_exit_frame() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Info, "ping".to_owned()),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Span, "outer".to_owned()),
+        );
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Span, "inner".to_owned()),
+        );
+        let mut table = Table::new(entries);
+        let depths = |table: &Table, bytes: &[u8]| {
+            let mut stream = StreamDecoder::new(table);
+            stream.received(bytes);
+            let mut frames = vec![];
+            while let Ok(frame) = stream.decode() {
+                frames.push((frame.depth(), frame.display_message().to_string()));
+            }
+            frames
+        };
+
+        // [index, kind, ID]
+        let frames = depths(
+            &table,
+            &[
+                1, 0, 0, 0, 0, // enter `outer`
+                2, 0, 0, 1, 0, // enter `inner`
+                // the firmware is reset; span IDs start over
+                1, 0, 0, 0, 0, // enter `outer`
+                0, 0, // ping
+                2, 0, 0, 1, 0, // enter `inner`; its exit frame is lost
+                1, 0, 1, 0, 0, // exit `outer`
+                0, 0, // ping
+            ],
+        );
+        assert_eq!(
+            frames,
+            [
+                (0, "→ outer".to_owned()),
+                (1, "→ inner".to_owned()),
+                (0, "→ outer".to_owned()),
+                (1, "ping".to_owned()),
+                (1, "→ inner".to_owned()),
+                (0, "← outer".to_owned()),
+                (0, "ping".to_owned()),
+            ]
+        );
+
+        // [index, sequence number, kind, ID]
+        table.set_sequence_numbers(true);
+        let frames = depths(
+            &table,
+            &[
+                1, 0, 0, 0, 0, 0, // enter `outer`
+                2, 0, 1, 0, 1, 0, // enter `inner`; its exit frame is lost
+                0, 0, 3, // ping
+                1, 0, 4, 1, 0, 0, // exit `outer`
+            ],
+        );
+        assert_eq!(
+            frames,
+            [
+                (0, "→ outer".to_owned()),
+                (1, "→ inner".to_owned()),
+                (0, "ping".to_owned()),
+                (0, "← outer".to_owned()),
+            ]
+        );
+    }
+}
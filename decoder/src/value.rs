@@ -0,0 +1,516 @@
+//! Typed access to the arguments of a decoded log frame
+
+use std::slice;
+
+use serde::{
+    ser::{SerializeMap, SerializeStruct},
+    Serialize, Serializer,
+};
+
+use crate::{Arg, FormatSliceElement};
+
+/// A decoded argument
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    Bool(bool),
+    /// `u8`, `u16`, `u24`, `u32`, `u64`, `u128` and `usize`
+    ///
+    /// A bitfield argument (e.g. `{0=0..4}`) is the raw value all bitfields of the argument were
+    /// taken from.
+    Unsigned(u128),
+    /// `i8`, `i16`, `i32`, `i64`, `i128` and `isize`
+    Signed(i128),
+    F32(f32),
+    F64(f64),
+    Char(char),
+    /// A string, `{=str}`, or an interned string, `{=istr}`
+    Str(&'a str),
+    /// A byte slice, `{=[u8]}`, or array, `{=[u8; N]}`
+    Bytes(&'a [u8]),
+    /// A value that was formatted on the target with `Debug2Format` or `Display2Format`
+    Preformatted(&'a str),
+    /// A value that implements `Format`, `{=?}`
+    Format(FormatValue<'a>),
+    /// A slice, `{=[?]}`, or array, `{=[?; N]}`, of values that implement `Format`
+    FormatSlice(Vec<FormatValue<'a>>),
+    /// The values written by a `Formatter` builder, e.g. the entries of `debug_list`
+    FormatSequence(Vec<FormatValue<'a>>),
+}
+
+impl<'a> Value<'a> {
+    pub(crate) fn new(arg: &'a Arg<'a>) -> Self {
+        match arg {
+            Arg::Bool(x) => Value::Bool(*x),
+            Arg::F32(x) => Value::F32(*x),
+            Arg::F64(x) => Value::F64(*x),
+            Arg::Uxx(x) => Value::Unsigned(*x),
+            Arg::Ixx(x) => Value::Signed(*x),
+            Arg::Str(x) => Value::Str(x),
+            Arg::IStr(x) => Value::Str(x),
+            Arg::Format { format, args } => Value::Format(FormatValue { format, args }),
+            Arg::FormatSlice { elements } => Value::FormatSlice(format_values(elements)),
+            Arg::FormatSequence { elements } => Value::FormatSequence(format_values(elements)),
+            Arg::Slice(x) => Value::Bytes(x),
+            Arg::Char(x) => Value::Char(*x),
+            Arg::Preformatted(x) => Value::Preformatted(x),
+        }
+    }
+}
+
+fn format_values<'a>(elements: &'a [FormatSliceElement<'a>]) -> Vec<FormatValue<'a>> {
+    elements
+        .iter()
+        .map(|element| FormatValue {
+            format: element.format,
+            args: &element.args,
+        })
+        .collect()
+}
+
+/// Format strings of the values written by `Formatter::debug_struct` and `debug_tuple`, and of
+/// the fields of the former
+const BUILDER_STRUCT: &str = "{=istr} {{ {=__internal_FormatSequence} }}";
+const BUILDER_TUPLE: &str = "{=istr}({=__internal_FormatSequence})";
+const BUILDER_FIELD: &str = "{=istr}: {=?}";
+
+/// A value of a type that implements `Format`
+///
+/// For an enum this is the value of the variant; e.g. the format string of `Some(1u8)` is
+/// `Some({=u8})`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatValue<'a> {
+    format: &'a str,
+    args: &'a [Arg<'a>],
+}
+
+impl<'a> FormatValue<'a> {
+    /// Returns the format string of the value
+    pub fn format(&self) -> &'a str {
+        self.format
+    }
+
+    /// Iterates over the arguments of the format string
+    pub fn args(&self) -> Args<'a> {
+        Args::new(self.args)
+    }
+
+    /// Returns the name of the type (or enum variant), if the format string was generated by
+    /// `#[derive(Format)]` or the value was written by `Formatter::debug_struct` or `debug_tuple`
+    pub fn name(&self) -> Option<&'a str> {
+        match (self.format, self.args) {
+            (BUILDER_STRUCT, [Arg::IStr(name), _]) | (BUILDER_TUPLE, [Arg::IStr(name), _]) => {
+                Some(name)
+            }
+            _ => parse_derived(self.format).map(|(name, _)| name),
+        }
+    }
+
+    /// Returns the fields of the value, if the format string was generated by `#[derive(Format)]`
+    /// or the value was written by `Formatter::debug_struct` or `debug_tuple`
+    ///
+    /// `Format` implementations in `defmt` for `core` types, like `Option`, use the same format
+    /// strings and are supported too.
+    pub fn fields(&self) -> Option<Fields<'a>> {
+        match (self.format, self.args) {
+            (BUILDER_STRUCT, [_, Arg::FormatSequence { elements }]) => {
+                return elements
+                    .iter()
+                    .map(|element| match (element.format, element.args.as_slice()) {
+                        (BUILDER_FIELD, [Arg::IStr(name), value]) => {
+                            Some((*name, Value::new(value)))
+                        }
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(Fields::Named);
+            }
+            (BUILDER_TUPLE, [_, Arg::FormatSequence { elements }]) => {
+                return Some(Fields::Tuple(
+                    format_values(elements)
+                        .into_iter()
+                        .map(Value::Format)
+                        .collect(),
+                ));
+            }
+            _ => {}
+        }
+
+        let (_, names) = parse_derived(self.format)?;
+        match names {
+            FieldNames::Unit if self.args.is_empty() => Some(Fields::Unit),
+            FieldNames::Tuple(len) if len == self.args.len() => {
+                Some(Fields::Tuple(self.args().collect()))
+            }
+            FieldNames::Named(names) if names.len() == self.args.len() => {
+                Some(Fields::Named(names.into_iter().zip(self.args()).collect()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The fields of a struct or enum variant
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fields<'a> {
+    /// `Foo`
+    Unit,
+    /// `Foo(x, y)`
+    Tuple(Vec<Value<'a>>),
+    /// `Foo { x: 1, y: 2 }`
+    Named(Vec<(&'a str, Value<'a>)>),
+}
+
+/// Iterator over the arguments of a format string
+///
+/// Each argument is yielded once, even if the format string refers to it several times.
+#[derive(Clone, Debug)]
+pub struct Args<'a> {
+    inner: slice::Iter<'a, Arg<'a>>,
+}
+
+impl<'a> Args<'a> {
+    pub(crate) fn new(args: &'a [Arg<'a>]) -> Self {
+        Self { inner: args.iter() }
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Value<'a>> {
+        self.inner.next().map(Value::new)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Args<'_> {}
+
+/// Numbers, booleans and strings are serialized as such; byte slices as sequences of numbers;
+/// `Format` values as `{"format": .., "args": [..], "fields": ..}`, where `fields` is only
+/// present if [`FormatValue::fields`] returns `Some`
+impl Serialize for Value<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Value::Bool(x) => serializer.serialize_bool(*x),
+            Value::Unsigned(x) => serializer.serialize_u128(*x),
+            Value::Signed(x) => serializer.serialize_i128(*x),
+            Value::F32(x) => serializer.serialize_f32(*x),
+            Value::F64(x) => serializer.serialize_f64(*x),
+            Value::Char(x) => serializer.serialize_char(*x),
+            Value::Str(x) | Value::Preformatted(x) => serializer.serialize_str(x),
+            Value::Bytes(x) => serializer.collect_seq(x.iter()),
+            Value::Format(x) => x.serialize(serializer),
+            Value::FormatSlice(x) | Value::FormatSequence(x) => serializer.collect_seq(x),
+        }
+    }
+}
+
+impl Serialize for FormatValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let fields = self.fields();
+        let len = if fields.is_some() { 3 } else { 2 };
+        let mut value = serializer.serialize_struct("FormatValue", len)?;
+        value.serialize_field("format", self.format)?;
+        value.serialize_field("args", &self.args().collect::<Vec<_>>())?;
+        match fields {
+            Some(fields) => value.serialize_field("fields", &fields)?,
+            None => value.skip_field("fields")?,
+        }
+        value.end()
+    }
+}
+
+/// `Unit` is serialized as a unit (`null`), `Tuple` as a sequence and `Named` as a map
+impl Serialize for Fields<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Fields::Unit => serializer.serialize_unit(),
+            Fields::Tuple(values) => serializer.collect_seq(values),
+            Fields::Named(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (name, value) in fields {
+                    map.serialize_entry(name, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+enum FieldNames<'a> {
+    Unit,
+    Tuple(usize),
+    Named(Vec<&'a str>),
+}
+
+/// Parses format strings generated by `#[derive(Format)]`: `Foo`, `Foo({=u8}, {=?})` or
+/// `Foo {{ x: {=u8}, y: {=?} }}`
+fn parse_derived(format: &str) -> Option<(&str, FieldNames<'_>)> {
+    let is_ident = |s: &str| {
+        s.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && matches!(s.chars().next(), Some(c) if !c.is_numeric())
+    };
+    // derived format strings only contain `{=type}` parameters
+    let is_param = |s: &str| {
+        s.starts_with("{=") && s.ends_with('}') && !s[1..s.len() - 1].contains(&['{', '}'][..])
+    };
+
+    let name_len = format
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(format.len());
+    let (name, rest) = format.split_at(name_len);
+    if !is_ident(name) {
+        return None;
+    }
+
+    let names = if rest.is_empty() {
+        FieldNames::Unit
+    } else if let Some(fields) = strip_affixes(rest, "(", ")") {
+        let fields = fields.split(", ").collect::<Vec<_>>();
+        if !fields.iter().all(|field| is_param(field)) {
+            return None;
+        }
+        FieldNames::Tuple(fields.len())
+    } else if let Some(fields) = strip_affixes(rest, " {{ ", " }}") {
+        let names = fields
+            .split(", ")
+            .map(|field| {
+                let (name, param) = field.split_once(": ")?;
+                if is_ident(name) && is_param(param) {
+                    Some(name)
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        FieldNames::Named(names)
+    } else {
+        return None;
+    };
+
+    Some((name, names))
+}
+
+fn strip_affixes<'a>(s: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    let s = s.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{Table, TableEntry, Tag};
+
+    fn table() -> Table {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(
+                Tag::Info,
+                "{=u8} {=i16} {=str} {=[u8]} {=?} {=[?]} {=?}".to_owned(),
+            ),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Derived, "Foo {{ x: {=u8}, y: {=?} }}".to_owned()),
+        );
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Prim, "None|Some({=?})".to_owned()),
+        );
+        entries.insert(
+            3,
+            TableEntry::new_without_symbol(Tag::Derived, "Unit".to_owned()),
+        );
+        Table::new(entries)
+    }
+
+    #[test]
+    fn frame_args() {
+        let table = table();
+        let bytes = [
+            0, 0,  // index
+            42, // u8
+            0xfe, 0xff, // i16
+            2, 0, 0, 0, b'h', b'i', // str
+            1, 0, 0, 0, 7, // [u8]
+            1, 0, 9, 3, 0, // Foo { x: 9, y: Unit }
+            2, 0, 0, 0, 2, 0, 1, 3, 0, 0, // [Some(Unit), None]
+            3, 0, // Unit
+        ];
+        let (frame, _) = table.decode(&bytes).unwrap();
+        let args = frame.args().collect::<Vec<_>>();
+        assert_eq!(args.len(), 7);
+        assert_eq!(args[0], Value::Unsigned(42));
+        assert_eq!(args[1], Value::Signed(-2));
+        assert_eq!(args[2], Value::Str("hi"));
+        assert_eq!(args[3], Value::Bytes(&[7]));
+
+        let foo = match &args[4] {
+            Value::Format(foo) => foo,
+            _ => panic!("expected a `Format` value"),
+        };
+        assert_eq!(foo.format(), "Foo {{ x: {=u8}, y: {=?} }}");
+        assert_eq!(foo.name(), Some("Foo"));
+        let fields = match foo.fields() {
+            Some(Fields::Named(fields)) => fields,
+            _ => panic!("expected named fields"),
+        };
+        assert_eq!(fields[0], ("x", Value::Unsigned(9)));
+        assert_eq!(fields[1].0, "y");
+        match &fields[1].1 {
+            Value::Format(unit) => assert_eq!(unit.fields(), Some(Fields::Unit)),
+            _ => panic!("expected a `Format` value"),
+        }
+
+        let elements = match &args[5] {
+            Value::FormatSlice(elements) => elements,
+            _ => panic!("expected a format slice"),
+        };
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].name(), Some("Some"));
+        assert!(matches!(elements[0].fields(), Some(Fields::Tuple(fields)) if fields.len() == 1));
+        assert_eq!(elements[1].name(), Some("None"));
+        assert_eq!(elements[1].fields(), Some(Fields::Unit));
+    }
+
+    #[test]
+    fn builder_fields() {
+        let formats = vec![
+            (Tag::Info, "{=?} {=?}"),
+            (Tag::Prim, "{=istr} {{ {=__internal_FormatSequence} }}"),
+            (Tag::Prim, "{=istr}({=__internal_FormatSequence})"),
+            (Tag::Prim, "{=istr}: {=?}"),
+            (Tag::Prim, "{=u8}"),
+            (Tag::Str, "Header"),
+            (Tag::Str, "src"),
+        ];
+        let entries = formats
+            .into_iter()
+            .enumerate()
+            .map(|(index, (tag, format))| {
+                (
+                    index,
+                    TableEntry::new_without_symbol(tag, format.to_owned()),
+                )
+            })
+            .collect();
+        let table = Table::new(entries);
+        let bytes = [
+            0, 0, // index
+            1, 0, 5, 0, 1, 3, 0, 6, 0, 4, 0, 1, 0, // Header { src: 1 }
+            2, 0, 5, 0, 1, 4, 0, 2, 0, // Header(2)
+        ];
+        let (frame, _) = table.decode(&bytes).unwrap();
+        let args = frame.args().collect::<Vec<_>>();
+
+        let header = match &args[0] {
+            Value::Format(header) => header,
+            _ => panic!("expected a `Format` value"),
+        };
+        assert_eq!(header.name(), Some("Header"));
+        let fields = match header.fields() {
+            Some(Fields::Named(fields)) => fields,
+            _ => panic!("expected named fields"),
+        };
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].0, "src");
+        match &fields[0].1 {
+            Value::Format(src) => assert_eq!(src.args().collect::<Vec<_>>(), [Value::Unsigned(1)]),
+            _ => panic!("expected a `Format` value"),
+        }
+
+        let header = match &args[1] {
+            Value::Format(header) => header,
+            _ => panic!("expected a `Format` value"),
+        };
+        assert_eq!(header.name(), Some("Header"));
+        assert!(matches!(header.fields(), Some(Fields::Tuple(fields)) if fields.len() == 1));
+    }
+
+    #[test]
+    fn serialize() {
+        let mut table = table();
+        table.set_timestamp_entry(TableEntry::new_without_symbol(
+            Tag::Timestamp,
+            "{=u8:µs}".to_owned(),
+        ));
+        let bytes = [
+            0, 0,  // index
+            7,  // timestamp
+            42, // u8
+            0xfe, 0xff, // i16
+            2, 0, 0, 0, b'h', b'i', // str
+            1, 0, 0, 0, 7, // [u8]
+            1, 0, 9, 3, 0, // Foo { x: 9, y: Unit }
+            1, 0, 0, 0, 2, 0, 0, // [None]
+            3, 0, // Unit
+        ];
+        let (frame, _) = table.decode(&bytes).unwrap();
+
+        let foo = serde_json::json!({
+            "format": "Foo {{ x: {=u8}, y: {=?} }}",
+            "args": [9, { "format": "Unit", "args": [], "fields": null }],
+            "fields": { "x": 9, "y": { "format": "Unit", "args": [], "fields": null } },
+        });
+        let none = serde_json::json!({ "format": "None", "args": [], "fields": null });
+        let unit = serde_json::json!({ "format": "Unit", "args": [], "fields": null });
+        assert_eq!(
+            serde_json::to_value(&frame).unwrap(),
+            serde_json::json!({
+                "index": 0,
+                "level": "info",
+                "timestamp": { "rendered": "0.000007", "raw": [7] },
+                "message": "42 -2 hi [7] Foo { x: 9, y: Unit } [None] Unit",
+                "format": "{=u8} {=i16} {=str} {=[u8]} {=?} {=[?]} {=?}",
+                "args": [42, -2, "hi", [7], foo, [none], unit],
+                "fields": {},
+                "span": null,
+                "depth": 0,
+                "sequence_number": null,
+                "lost_frames": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn derived_format_strings() {
+        assert!(matches!(
+            parse_derived("Foo"),
+            Some(("Foo", FieldNames::Unit))
+        ));
+        assert!(matches!(
+            parse_derived("Foo({=u8}, {=?})"),
+            Some(("Foo", FieldNames::Tuple(2)))
+        ));
+        assert!(matches!(
+            parse_derived("Foo {{ a: {=u8}, b_2: {=[?]} }}"),
+            Some(("Foo", FieldNames::Named(names))) if names == ["a", "b_2"]
+        ));
+
+        assert!(parse_derived("").is_none());
+        assert!(parse_derived("x = {=u8}").is_none());
+        assert!(parse_derived("Foo()").is_none());
+        assert!(parse_derived("Foo(x)").is_none());
+        assert!(parse_derived("Foo {{ a }}").is_none());
+        assert!(parse_derived("Foo {{ a: {0=0..4} {0=4..8} }}").is_none());
+    }
+}
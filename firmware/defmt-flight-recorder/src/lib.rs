@@ -0,0 +1,132 @@
+//! [`defmt`](https://github.com/knurling-rs/defmt) global logger that keeps the most recent log
+//! frames in RAM and only sends them to the host when flushed.
+//!
+//! This lets an application log at a high rate -- more than its transport could handle -- and
+//! still get the log leading up to a failure. The oldest frames are dropped, as a whole, when the
+//! buffer is full. The buffer is flushed by [`defmt::flush`], which `defmt::panic!` and
+//! `panic-probe` (with its `print-defmt` feature) call before faulting.
+//!
+//! To use this crate, link to it by importing it somewhere in your project and register the
+//! transport the frames are flushed to.
+//!
+//! ``` ignore
+//! // src/main.rs or src/bin/my-app.rs
+//! use defmt_flight_recorder::Transport;
+//!
+//! struct Uart;
+//!
+//! impl Transport for Uart {
+//!     fn write(bytes: &[u8]) {
+//!         // ..
+//!     }
+//! }
+//!
+//! defmt_flight_recorder::transport!(Uart);
+//! ```
+//!
+//! As frames are dropped between flushes the host may report lost frames, if `defmt`'s
+//! `sequence-numbers` feature is enabled; the flushed data always starts at a frame boundary.
+//! With `defmt`'s `build-id` feature the flushed data ends with the build ID frame, which is sent
+//! again on every flush.
+
+#![no_std]
+
+mod ring;
+
+use cortex_m::{interrupt, register};
+
+use crate::ring::FrameRing;
+
+// TODO make configurable
+// NOTE must be a power of 2
+const SIZE: usize = 4096;
+
+/// The destination of the flushed log frames; registered with [`transport!`]
+pub trait Transport {
+    /// Writes `bytes` to the host. The write operation must not fail.
+    ///
+    /// This is called with interrupts disabled so it should not wait for an interrupt handler.
+    fn write(bytes: &[u8]);
+}
+
+/// Registers the [`Transport`] the log frames are flushed to
+///
+/// ``` ignore
+/// defmt_flight_recorder::transport!(Uart);
+/// ```
+#[macro_export]
+macro_rules! transport {
+    ($ty:ty) => {
+        #[no_mangle]
+        fn _defmt_flight_recorder_write(bytes: &[u8]) {
+            <$ty as $crate::Transport>::write(bytes)
+        }
+    };
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+// NOTE(static mut) these are only accessed with interrupts disabled
+
+/// `Some` while the logger is acquired; holds whether interrupts were enabled before that
+static mut ACQUIRED: Option<bool> = None;
+
+static mut RING: FrameRing<SIZE> = FrameRing::new();
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let primask = register::primask::read();
+        interrupt::disable();
+
+        // NOTE(unsafe) interrupts are disabled
+        unsafe {
+            if ACQUIRED.is_some() {
+                panic!("defmt logger taken reentrantly")
+            }
+            ACQUIRED = Some(primask.is_active());
+
+            // one log frame is written per `acquire` / `release` pair
+            RING.start_frame()
+        }
+    }
+
+    unsafe fn release() {
+        RING.end_frame();
+
+        if ACQUIRED.take() == Some(true) {
+            // the logger was acquired with interrupts enabled
+            interrupt::enable()
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        RING.write(bytes)
+    }
+
+    fn flush() {
+        extern "Rust" {
+            fn _defmt_flight_recorder_write(bytes: &[u8]);
+        }
+
+        let primask = register::primask::read();
+        interrupt::disable();
+
+        // NOTE(unsafe) interrupts are disabled and the logger is not acquired in this execution
+        // context (see `Logger::flush`) so the ring is not being written to
+        unsafe {
+            // the build ID frame (`build-id` feature) has most likely been dropped by now; append
+            // it again so the host can check it
+            RING.start_frame();
+            defmt::export::send_build_id();
+            RING.end_frame();
+
+            while RING.pop(|bytes| _defmt_flight_recorder_write(bytes)) {}
+        }
+
+        if primask.is_active() {
+            // NOTE(unsafe) restores the state before this function was called
+            unsafe { interrupt::enable() }
+        }
+    }
+}
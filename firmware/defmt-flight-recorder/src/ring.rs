@@ -0,0 +1,203 @@
+//! Ring buffer of complete log frames
+//!
+//! `FrameRing` is plain data; locking and interrupt handling are up to the logger in `lib.rs`.
+
+use core::ops::Range;
+
+/// Every frame is prefixed with its length, a little endian `u16`
+const PREFIX: usize = 2;
+
+/// A ring buffer of `N` (a power of 2) bytes that drops the oldest frames to make room for new
+/// ones
+///
+/// Frames are dropped as a whole so the data read out of the ring always starts at a frame
+/// boundary.
+pub(crate) struct FrameRing<const N: usize> {
+    buffer: [u8; N],
+    /// Free-running counter of the bytes read; the position in the buffer is the counter modulo `N`
+    read: usize,
+    /// Free-running counter of the bytes written
+    write: usize,
+    /// Start (length prefix) of the frame that's being written; `None` between frames and when
+    /// the frame being written has been dropped
+    frame: Option<usize>,
+}
+
+impl<const N: usize> FrameRing<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            read: 0,
+            write: 0,
+            frame: None,
+        }
+    }
+
+    pub(crate) fn start_frame(&mut self) {
+        debug_assert!(N.is_power_of_two());
+
+        self.make_room(PREFIX);
+        // the length is filled in by `end_frame`
+        self.frame = Some(self.write);
+        self.write = self.write.wrapping_add(PREFIX);
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        let start = match self.frame {
+            Some(start) => start,
+            None => return,
+        };
+
+        let len = self.write.wrapping_sub(start) + bytes.len();
+        if len > N || len - PREFIX > usize::from(u16::MAX) {
+            // the frame doesn't fit; drop it
+            self.write = start;
+            self.frame = None;
+            return;
+        }
+
+        self.make_room(bytes.len());
+        self.copy_in(self.write, bytes);
+        self.write = self.write.wrapping_add(bytes.len());
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        if let Some(start) = self.frame.take() {
+            let len = (self.write.wrapping_sub(start) - PREFIX) as u16;
+            self.copy_in(start, &len.to_le_bytes());
+        }
+    }
+
+    /// Removes the oldest complete frame from the ring and passes it to `f`, in up to two parts
+    ///
+    /// Returns `false` if there are no complete frames.
+    pub(crate) fn pop(&mut self, mut f: impl FnMut(&[u8])) -> bool {
+        let end = match self.frame {
+            Some(start) => start,
+            // a frame that's being dropped doesn't occupy any space
+            None => self.write,
+        };
+        if self.read == end {
+            return false;
+        }
+
+        let len = self.frame_len();
+        let start = self.read.wrapping_add(PREFIX);
+        for region in Self::regions(start, len) {
+            if !region.is_empty() {
+                f(&self.buffer[region]);
+            }
+        }
+        self.read = start.wrapping_add(len);
+        true
+    }
+
+    /// Drops the oldest frames until there's room for `n` more bytes
+    fn make_room(&mut self, n: usize) {
+        // NOTE this never drops the frame that's being written: `write` already checked that the
+        // frame fits in the ring
+        while N - self.write.wrapping_sub(self.read) < n {
+            self.read = self.read.wrapping_add(PREFIX + self.frame_len());
+        }
+    }
+
+    /// Length of the oldest frame, without the length prefix
+    fn frame_len(&self) -> usize {
+        let [head, tail] = Self::regions(self.read, PREFIX);
+        let mut prefix = [0; PREFIX];
+        prefix[..head.len()].copy_from_slice(&self.buffer[head.clone()]);
+        prefix[head.len()..].copy_from_slice(&self.buffer[tail]);
+        usize::from(u16::from_le_bytes(prefix))
+    }
+
+    /// Writes `bytes` at byte counter `at`, wrapping around the end of the buffer
+    fn copy_in(&mut self, at: usize, bytes: &[u8]) {
+        let [head, tail] = Self::regions(at, bytes.len());
+        let (first, second) = bytes.split_at(head.len());
+        self.buffer[head].copy_from_slice(first);
+        self.buffer[tail].copy_from_slice(second);
+    }
+
+    /// The parts of `buffer` occupied by the `len` bytes at byte counter `at`: the part up to the
+    /// end of the buffer and the part that wrapped around to its start, which may be empty
+    fn regions(at: usize, len: usize) -> [Range<usize>; 2] {
+        let start = at & (N - 1);
+        let end = start + len;
+        if end <= N {
+            [start..end, 0..0]
+        } else {
+            [start..N, 0..end - N]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log<const N: usize>(ring: &mut FrameRing<N>, parts: &[&[u8]]) {
+        ring.start_frame();
+        for part in parts {
+            ring.write(part);
+        }
+        ring.end_frame();
+    }
+
+    fn drain<const N: usize>(ring: &mut FrameRing<N>) -> Vec<Vec<u8>> {
+        let mut frames = vec![];
+        loop {
+            let mut frame = vec![];
+            if !ring.pop(|part| frame.extend_from_slice(part)) {
+                return frames;
+            }
+            frames.push(frame);
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let mut ring = FrameRing::<16>::new();
+        log(&mut ring, &[&[1, 2], &[3]]);
+        log(&mut ring, &[]);
+        log(&mut ring, &[&[4]]);
+        assert_eq!(drain(&mut ring), [vec![1, 2, 3], vec![], vec![4]]);
+        assert!(drain(&mut ring).is_empty());
+
+        // wraps around
+        log(&mut ring, &[&[5, 6, 7, 8, 9, 10, 11, 12]]);
+        assert_eq!(drain(&mut ring), [vec![5, 6, 7, 8, 9, 10, 11, 12]]);
+    }
+
+    #[test]
+    fn drops_oldest_frames() {
+        let mut ring = FrameRing::<16>::new();
+        log(&mut ring, &[&[1, 2, 3]]);
+        log(&mut ring, &[&[4, 5, 6]]);
+        log(&mut ring, &[&[7, 8, 9]]);
+        // 3 * 5 bytes are in use; the first two frames make room for this one
+        log(&mut ring, &[&[10, 11], &[12, 13, 14]]);
+        assert_eq!(drain(&mut ring), [vec![7, 8, 9], vec![10, 11, 12, 13, 14]]);
+    }
+
+    #[test]
+    fn drops_frames_that_dont_fit() {
+        let mut ring = FrameRing::<8>::new();
+        log(&mut ring, &[&[1]]);
+        log(&mut ring, &[&[2, 3, 4], &[5, 6, 7, 8]]);
+        log(&mut ring, &[&[9]]);
+        assert_eq!(drain(&mut ring), [vec![1], vec![9]]);
+    }
+
+    #[test]
+    fn pop_while_writing() {
+        let mut ring = FrameRing::<16>::new();
+        log(&mut ring, &[&[1]]);
+        ring.start_frame();
+        ring.write(&[2]);
+        // only complete frames are popped
+        assert_eq!(drain(&mut ring), [vec![1]]);
+        ring.write(&[3]);
+        ring.end_frame();
+        assert_eq!(drain(&mut ring), [vec![2, 3]]);
+    }
+}
@@ -0,0 +1,161 @@
+//! [`defmt`](https://github.com/knurling-rs/defmt) global logger that keeps the log in RAM across
+//! resets.
+//!
+//! Log frames are stored in a ring buffer in the `.uninit` section, which is not initialized on
+//! boot. After a reset -- a hard fault, a watchdog reset, a panic that resets the device -- the
+//! frames logged before the reset are still there and can be sent to the host over any transport
+//! (UART, USB, radio, ...). When the buffer is full the oldest frames are overwritten.
+//!
+//! To use this crate, link to it by importing it somewhere in your project and call [`init`]
+//! early on.
+//!
+//! ``` ignore
+//! // src/main.rs or src/bin/my-app.rs
+//! use defmt_persist as _;
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!     if defmt_persist::init() != 0 {
+//!         // send the log of the previous boot to the host
+//!         defmt_persist::drain_previous(|bytes| uart.bwrite_all(bytes).unwrap());
+//!     }
+//!     // ..
+//! }
+//! ```
+//!
+//! Frames logged before `init` is called are dropped.
+//!
+//! The `.uninit` section is provided by the linker script of `cortex-m-rt` 0.7 or newer; make sure
+//! your application depends on such a version. Older versions don't define the section, so the
+//! linker may place the buffer in memory that doesn't survive a reset (or fail to link).
+//!
+//! As the oldest frames are overwritten the drained data may start in the middle of a frame; use
+//! `defmt_decoder::StreamDecoder::new_mid_stream` to decode it. The `encoding-rzcobs` feature of
+//! `defmt` is recommended as it lets the decoder find the start of the next frame reliably.
+//!
+//! The build ID frame (`build-id` feature of `defmt`) is only sent ahead of the first log frame
+//! after a boot so it is usually overwritten by the time the log is drained, and the previous
+//! boot may have run a different firmware. Make sure to decode the drained log with the ELF file
+//! of the firmware that logged it.
+
+#![no_std]
+
+mod ring;
+
+use core::{
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use cortex_m::{interrupt, register};
+
+use crate::ring::{Header, Ring};
+
+// TODO make configurable
+// NOTE must be a power of 2
+const SIZE: usize = 1024;
+
+#[defmt::global_logger]
+struct Logger;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+static INTERRUPTS_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let primask = register::primask::read();
+        interrupt::disable();
+
+        if TAKEN.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly")
+        }
+
+        // no need for CAS because interrupts are disabled
+        TAKEN.store(true, Ordering::Relaxed);
+
+        INTERRUPTS_ACTIVE.store(primask.is_active(), Ordering::Relaxed);
+    }
+
+    unsafe fn release() {
+        TAKEN.store(false, Ordering::Relaxed);
+        if INTERRUPTS_ACTIVE.load(Ordering::Relaxed) {
+            // re-enable interrupts
+            interrupt::enable()
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        // NOTE(unsafe) interrupts are disabled; `RING` is only accessed in critical sections
+        if let Some(ring) = &mut RING {
+            ring.write(bytes)
+        }
+    }
+}
+
+#[repr(C)]
+struct Storage {
+    header: MaybeUninit<Header>,
+    buffer: [u8; SIZE],
+}
+
+// NOTE the section is not initialized on boot so this holds whatever was in RAM before the reset;
+// `Ring::recover` checks the header before trusting any of it
+#[cfg_attr(target_os = "macos", link_section = ".uninit,defmt-persist.STORAGE")]
+#[cfg_attr(
+    not(target_os = "macos"),
+    link_section = ".uninit.defmt-persist.STORAGE"
+)]
+static mut STORAGE: MaybeUninit<Storage> = MaybeUninit::uninit();
+
+static mut RING: Option<Ring<'static>> = None;
+
+/// End (write position) of the data logged before the last reset
+static mut PREVIOUS_END: u32 = 0;
+
+/// Takes over the log buffer
+///
+/// Returns the number of bytes logged before the last reset that can be drained with
+/// [`drain_previous`]; this is 0 after a power-on reset.
+///
+/// # Panics
+///
+/// This function panics if called more than once.
+pub fn init() -> usize {
+    interrupt::free(|_| unsafe {
+        // NOTE(unsafe) `RING` is only accessed in critical sections and it's only written here
+        if RING.is_some() {
+            panic!("defmt_persist::init called more than once")
+        }
+
+        // NOTE(unsafe) `STORAGE` is RAM that is not initialized on boot
+        let storage = STORAGE.as_mut_ptr();
+        let header = &mut *ptr::addr_of_mut!((*storage).header);
+        let buffer = &mut *ptr::addr_of_mut!((*storage).buffer);
+        let (ring, len) = Ring::recover(header, buffer);
+        PREVIOUS_END = ring.write_position();
+        RING = Some(ring);
+        len
+    })
+}
+
+/// Drains the data logged before the last reset, passing it to `f` in chunks
+///
+/// `f` is called outside of critical sections so it may block and may even log.
+pub fn drain_previous(mut f: impl FnMut(&[u8])) {
+    let mut chunk = [0; 64];
+    loop {
+        let n = interrupt::free(|_| unsafe {
+            // NOTE(unsafe) `RING` is only accessed in critical sections
+            match &mut RING {
+                Some(ring) => ring.read(&mut chunk, PREVIOUS_END),
+                None => 0,
+            }
+        });
+
+        if n == 0 {
+            return;
+        }
+        f(&chunk[..n]);
+    }
+}
@@ -0,0 +1,272 @@
+//! Ring buffer whose state survives a reset
+//!
+//! This module doesn't touch any hardware so it can be tested on the host.
+
+use core::{mem::MaybeUninit, ptr};
+
+/// Marks a header that has been written by this crate
+const MAGIC: u32 = 0xdef7_9e55;
+
+/// Bookkeeping of the ring buffer; it lives in uninitialized memory next to the buffer
+///
+/// `read` and `write` are free-running byte counters; the position in the buffer is the counter
+/// modulo the capacity, which is a power of 2.
+#[repr(C)]
+pub(crate) struct Header {
+    magic: u32,
+    capacity: u32,
+    read: u32,
+    write: u32,
+    /// Guards against garbage that happens to start with `MAGIC`
+    check: u32,
+}
+
+impl Header {
+    pub(crate) const fn new() -> Self {
+        Self {
+            magic: 0,
+            capacity: 0,
+            read: 0,
+            write: 0,
+            check: 0,
+        }
+    }
+
+    fn checksum(&self) -> u32 {
+        self.magic ^ self.capacity.rotate_left(8) ^ self.read.rotate_left(16) ^ self.write
+    }
+
+    /// Whether the header describes a ring of `capacity` bytes, i.e. the buffer holds the data of
+    /// a previous boot
+    fn is_valid(&self, capacity: usize) -> bool {
+        self.magic == MAGIC
+            && self.capacity as usize == capacity
+            && self.check == self.checksum()
+            && self.write.wrapping_sub(self.read) as usize <= capacity
+    }
+
+    fn update(&mut self, read: u32, write: u32) {
+        self.read = read;
+        self.write = write;
+        self.check = self.checksum();
+    }
+}
+
+/// A ring buffer of `buffer.len()` (a power of 2) bytes that overwrites the oldest data when full
+pub(crate) struct Ring<'a> {
+    header: &'a mut Header,
+    buffer: &'a mut [u8],
+}
+
+impl<'a> Ring<'a> {
+    /// Takes over `header` and `buffer` after a reset
+    ///
+    /// Returns the ring and the amount of data left over from the previous boot; if `header` is
+    /// not valid (e.g. after a power-on reset) the ring starts out empty.
+    ///
+    /// # Safety
+    ///
+    /// `header` must be backed by memory that holds *some* bit pattern, like RAM that is not
+    /// initialized on boot, even if it has never been written by this program
+    pub(crate) unsafe fn recover(
+        header: &'a mut MaybeUninit<Header>,
+        buffer: &'a mut [u8],
+    ) -> (Self, usize) {
+        assert!(buffer.len().is_power_of_two() && buffer.len() <= 1 << 31);
+
+        // NOTE(volatile) the compiler must not assume anything about the contents of the memory;
+        // they were written before the reset, if at all
+        let previous = ptr::read_volatile(header.as_ptr());
+        let header = if previous.is_valid(buffer.len()) {
+            header.write(previous)
+        } else {
+            let mut empty = Header::new();
+            empty.magic = MAGIC;
+            empty.capacity = buffer.len() as u32;
+            empty.update(0, 0);
+            header.write(empty)
+        };
+
+        let ring = Self { header, buffer };
+        let len = ring.len();
+        (ring, len)
+    }
+
+    /// Number of bytes that have not been read yet
+    pub(crate) fn len(&self) -> usize {
+        self.header.write.wrapping_sub(self.header.read) as usize
+    }
+
+    /// Position of the write counter; marks the end of the data written so far
+    pub(crate) fn write_position(&self) -> u32 {
+        self.header.write
+    }
+
+    /// Appends `bytes`, overwriting the oldest data if the ring is full
+    pub(crate) fn write(&mut self, mut bytes: &[u8]) {
+        let capacity = self.buffer.len();
+        if bytes.len() > capacity {
+            // only the tail would survive anyway
+            let skipped = bytes.len() - capacity;
+            bytes = &bytes[skipped..];
+            self.header.write = self.header.write.wrapping_add(skipped as u32);
+        }
+
+        let mut write = self.header.write;
+        for chunk in split(write, bytes.len(), capacity) {
+            let (start, len) = chunk;
+            self.buffer[start..start + len].copy_from_slice(&bytes[..len]);
+            bytes = &bytes[len..];
+            write = write.wrapping_add(len as u32);
+        }
+
+        let mut read = self.header.read;
+        if write.wrapping_sub(read) as usize > capacity {
+            // the oldest data has been overwritten
+            read = write.wrapping_sub(capacity as u32);
+        }
+        self.header.update(read, write);
+    }
+
+    /// Moves up to `buf.len()` of the oldest bytes, but none written at or after the write
+    /// position `end`, into `buf`
+    ///
+    /// Returns the number of bytes moved.
+    pub(crate) fn read(&mut self, buf: &mut [u8], end: u32) -> usize {
+        let read = self.header.read;
+        let available = end.wrapping_sub(read) as i32;
+        if available <= 0 {
+            // nothing left, or the data before `end` has been overwritten
+            return 0;
+        }
+
+        let n = (available as usize).min(self.len()).min(buf.len());
+        let mut pos = 0;
+        for (start, len) in split(read, n, self.buffer.len()) {
+            buf[pos..pos + len].copy_from_slice(&self.buffer[start..start + len]);
+            pos += len;
+        }
+        self.header
+            .update(read.wrapping_add(n as u32), self.header.write);
+        n
+    }
+}
+
+/// Splits the `len` bytes starting at `counter` into (at most two) contiguous `(start, len)`
+/// regions of a ring of `capacity` bytes
+fn split(counter: u32, len: usize, capacity: usize) -> impl Iterator<Item = (usize, usize)> {
+    let start = counter as usize & (capacity - 1);
+    let first = len.min(capacity - start);
+    let second = len - first;
+    core::iter::once((start, first)).chain(if second == 0 { None } else { Some((0, second)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(ring: &mut Ring, end: u32) -> Vec<u8> {
+        let mut data = vec![];
+        let mut buf = [0; 3];
+        loop {
+            let n = ring.read(&mut buf, end);
+            if n == 0 {
+                return data;
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn write_read() {
+        let mut header = MaybeUninit::new(Header::new());
+        let mut buffer = [0; 8];
+        let (mut ring, len) = unsafe { Ring::recover(&mut header, &mut buffer) };
+        assert_eq!(len, 0);
+
+        ring.write(&[1, 2, 3]);
+        ring.write(&[4, 5]);
+        assert_eq!(ring.len(), 5);
+        let end = ring.write_position();
+        assert_eq!(drain(&mut ring, end), [1, 2, 3, 4, 5]);
+        assert_eq!(ring.len(), 0);
+
+        // wraps around
+        ring.write(&[6, 7, 8, 9, 10]);
+        let end = ring.write_position();
+        assert_eq!(drain(&mut ring, end), [6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn overwrites_oldest() {
+        let mut header = MaybeUninit::new(Header::new());
+        let mut buffer = [0; 8];
+        let (mut ring, _) = unsafe { Ring::recover(&mut header, &mut buffer) };
+
+        ring.write(&[1, 2, 3, 4, 5, 6]);
+        ring.write(&[7, 8, 9, 10]);
+        assert_eq!(ring.len(), 8);
+        let end = ring.write_position();
+        assert_eq!(drain(&mut ring, end), [3, 4, 5, 6, 7, 8, 9, 10]);
+
+        ring.write(&(0..20).collect::<Vec<_>>());
+        let end = ring.write_position();
+        assert_eq!(drain(&mut ring, end), [12, 13, 14, 15, 16, 17, 18, 19]);
+    }
+
+    #[test]
+    fn survives_reset() {
+        let mut header = MaybeUninit::new(Header::new());
+        let mut buffer = [0; 8];
+        let (mut ring, _) = unsafe { Ring::recover(&mut header, &mut buffer) };
+        ring.write(&[1, 2, 3]);
+
+        // reset
+        let (mut ring, len) = unsafe { Ring::recover(&mut header, &mut buffer) };
+        assert_eq!(len, 3);
+        let previous_end = ring.write_position();
+        ring.write(&[4, 5, 6, 7, 8, 9]);
+
+        // the oldest byte of the previous boot has been overwritten
+        assert_eq!(drain(&mut ring, previous_end), [2, 3]);
+        let end = ring.write_position();
+        assert_eq!(drain(&mut ring, end), [4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn previous_boot_overwritten() {
+        let mut header = MaybeUninit::new(Header::new());
+        let mut buffer = [0; 4];
+        let (mut ring, _) = unsafe { Ring::recover(&mut header, &mut buffer) };
+        ring.write(&[1, 2]);
+
+        let (mut ring, _) = unsafe { Ring::recover(&mut header, &mut buffer) };
+        let previous_end = ring.write_position();
+        ring.write(&[3, 4, 5, 6]);
+        assert!(drain(&mut ring, previous_end).is_empty());
+    }
+
+    #[test]
+    fn garbage_header() {
+        let mut header = MaybeUninit::new(Header {
+            magic: MAGIC,
+            capacity: 8,
+            read: 0,
+            write: 5,
+            check: 0,
+        });
+        let mut buffer = [0; 8];
+        let (ring, len) = unsafe { Ring::recover(&mut header, &mut buffer) };
+        assert_eq!(len, 0);
+        assert_eq!(ring.len(), 0);
+
+        // wrong capacity, e.g. after a firmware update
+        let mut wrong_capacity = Header::new();
+        wrong_capacity.magic = MAGIC;
+        wrong_capacity.capacity = 16;
+        wrong_capacity.update(0, 5);
+        let mut header = MaybeUninit::new(wrong_capacity);
+        let (_, len) = unsafe { Ring::recover(&mut header, &mut buffer) };
+        assert_eq!(len, 0);
+    }
+}
@@ -0,0 +1,138 @@
+//! Per-execution-context frame buffers (`per-context-buffers` feature)
+//!
+//! Every execution context encodes its log frames into a buffer of its own, with interrupts
+//! enabled, and commits complete frames to the RTT channel as a whole. Interrupts are only disabled
+//! for the few instructions that reserve space in, and publish data to, the channel; never while a
+//! frame is encoded or copied.
+//!
+//! Execution contexts preempt each other in a nested fashion so the buffers are used like a stack:
+//! a context takes the buffer at the current nesting depth and hands it back before the context it
+//! preempted resumes.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use cortex_m::interrupt;
+
+use crate::{handle, Channel, Logger, SIZE};
+
+// TODO make configurable
+/// Maximum number of execution contexts that can log at the same time, e.g. thread mode plus
+/// three nested interrupt priority levels; frames logged from deeper contexts are dropped
+const MAX_DEPTH: usize = 4;
+/// Maximum size of an encoded log frame; larger frames are dropped
+const FRAME_SIZE: usize = 256;
+
+/// Number of execution contexts that are currently logging
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+// NOTE(static mut) the buffer (and length) at index `i` is only accessed by the execution context
+// at nesting depth `i`
+static mut BUFFERS: [[u8; FRAME_SIZE]; MAX_DEPTH] = [[0; FRAME_SIZE]; MAX_DEPTH];
+/// Length of the frame in each buffer; `None` if the frame has been dropped
+static mut LENS: [Option<usize>; MAX_DEPTH] = [None; MAX_DEPTH];
+
+/// End of the channel data that has been reserved by `commit`; `Channel.write` lags behind while
+/// reserved space is being filled
+static RESERVED: AtomicUsize = AtomicUsize::new(0);
+/// Number of `commit` calls that are filling reserved space
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        // NOTE no read-modify-write operation needed: a context that preempts us here restores
+        // `DEPTH` before we resume
+        let depth = DEPTH.load(Ordering::Relaxed);
+        DEPTH.store(depth + 1, Ordering::Relaxed);
+
+        if depth < MAX_DEPTH {
+            // NOTE(unsafe) this context owns the buffer at `depth` until `release`
+            unsafe { LENS[depth] = Some(0) }
+        }
+    }
+
+    unsafe fn release() {
+        let depth = DEPTH.load(Ordering::Relaxed) - 1;
+
+        if depth < MAX_DEPTH {
+            if let Some(len) = LENS[depth] {
+                handle().commit(&BUFFERS[depth][..len]);
+            }
+        }
+
+        DEPTH.store(depth, Ordering::Relaxed);
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        let depth = DEPTH.load(Ordering::Relaxed) - 1;
+        if depth >= MAX_DEPTH {
+            return;
+        }
+
+        if let Some(len) = LENS[depth] {
+            if len + bytes.len() <= FRAME_SIZE {
+                BUFFERS[depth][len..len + bytes.len()].copy_from_slice(bytes);
+                LENS[depth] = Some(len + bytes.len());
+            } else {
+                // a truncated frame would corrupt the stream; drop the whole frame
+                LENS[depth] = None;
+            }
+        }
+    }
+}
+
+impl Channel {
+    /// Writes `frame` to the channel as a whole, or not at all if there's not enough room
+    ///
+    /// Unlike `write_all` this can be called from any execution context, even while another
+    /// context is in the middle of a `commit`.
+    fn commit(&self, frame: &[u8]) {
+        let cursor = interrupt::free(|_| {
+            let read = self.read.load(Ordering::Relaxed);
+            let reserved = RESERVED.load(Ordering::Relaxed);
+            let available = if read > reserved {
+                read - reserved - 1
+            } else {
+                SIZE - 1 - reserved + read
+            };
+            if frame.len() > available {
+                return None;
+            }
+
+            RESERVED.store((reserved + frame.len()) % SIZE, Ordering::Relaxed);
+            PENDING.store(PENDING.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+            Some(reserved)
+        });
+        let cursor = match cursor {
+            Some(cursor) => cursor,
+            // channel full; drop the frame
+            None => return,
+        };
+
+        let len = frame.len();
+        unsafe {
+            if cursor + len > SIZE {
+                // split memcpy
+                let pivot = SIZE - cursor;
+                ptr::copy_nonoverlapping(frame.as_ptr(), self.buffer.add(cursor), pivot);
+                ptr::copy_nonoverlapping(frame.as_ptr().add(pivot), self.buffer, len - pivot);
+            } else {
+                // single memcpy
+                ptr::copy_nonoverlapping(frame.as_ptr(), self.buffer.add(cursor), len);
+            }
+        }
+
+        interrupt::free(|_| {
+            let pending = PENDING.load(Ordering::Relaxed) - 1;
+            PENDING.store(pending, Ordering::Relaxed);
+            // the reserved space of the contexts we preempted may not have been filled yet; the
+            // outermost `commit` publishes the data of all of them
+            if pending == 0 {
+                self.write
+                    .store(RESERVED.load(Ordering::Relaxed), Ordering::Release);
+            }
+        });
+    }
+}
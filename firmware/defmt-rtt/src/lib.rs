@@ -8,14 +8,32 @@
 //! // src/main.rs or src/bin/my-app.rs
 //! use defmt_rtt as _;
 //! ```
+//!
+//! By default interrupts are disabled while a log frame is being written. With the
+//! `per-context-buffers` feature every execution context (thread mode, each interrupt priority
+//! level) encodes its log frames into a buffer of its own instead, with interrupts enabled, and
+//! complete frames are committed to the RTT channel as a whole. This keeps the interrupt latency
+//! low at the cost of some RAM; frames are dropped, rather than blocking, when the channel is
+//! full. Up to 4 nested contexts can log at the same time and frames can be up to 256 bytes long;
+//! other frames are dropped.
+//!
+//! NOTE with the `per-context-buffers` feature log frames are no longer written one at a time so
+//! it enables `defmt`'s `preemptible-logger` feature, which can't be combined with `defmt`'s
+//! `sequence-numbers` and `sinks` features nor, on ARMv6-M, with `defmt::span!`.
 
 #![no_std]
 
+#[cfg(feature = "per-context-buffers")]
+mod contexts;
+
+use core::sync::atomic::AtomicUsize;
+#[cfg(not(feature = "per-context-buffers"))]
 use core::{
     ptr,
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+#[cfg(not(feature = "per-context-buffers"))]
 use cortex_m::{interrupt, register};
 
 // TODO make configurable
@@ -25,9 +43,12 @@ const SIZE: usize = 1024;
 #[defmt::global_logger]
 struct Logger;
 
+#[cfg(not(feature = "per-context-buffers"))]
 static TAKEN: AtomicBool = AtomicBool::new(false);
+#[cfg(not(feature = "per-context-buffers"))]
 static INTERRUPTS_ACTIVE: AtomicBool = AtomicBool::new(false);
 
+#[cfg(not(feature = "per-context-buffers"))]
 unsafe impl defmt::Logger for Logger {
     fn acquire() {
         let primask = register::primask::read();
@@ -74,9 +95,11 @@ struct Channel {
     flags: AtomicUsize,
 }
 
+#[cfg(not(feature = "per-context-buffers"))]
 const BLOCK_IF_FULL: usize = 2;
 const NOBLOCK_TRIM: usize = 1;
 
+#[cfg(not(feature = "per-context-buffers"))]
 impl Channel {
     fn write_all(&self, mut bytes: &[u8]) {
         // NOTE `flags` is modified by the host after RAM initialization while the device is halted
@@ -165,7 +188,7 @@ impl Channel {
 // make sure we only get shared references to the header/channel (avoid UB)
 /// # Safety
 /// `Channel` API is not re-entrant; this handle should not be held from different execution
-/// contexts (e.g. thread-mode, interrupt context)
+/// contexts (e.g. thread-mode, interrupt context); `Channel::commit` is the exception
 unsafe fn handle() -> &'static Channel {
     // NOTE the `rtt-target` API is too permissive. It allows writing arbitrary data to any
     // channel (`set_print_channel` + `rprint*`) and that can corrupt defmt log frames.
@@ -12,7 +12,8 @@
 //! - `print-rtt`: Prints the panic message over plain RTT (via `rtt-target`). RTT must be
 //!   initialized by the app.
 //! - `print-defmt`: Prints the panic message via [defmt]'s transport (note that defmt will not be
-//!   used to efficiently format the message).
+//!   used to efficiently format the message). The global logger is flushed afterwards so loggers
+//!   that buffer log frames, like a flight recorder, send them before the device faults.
 //!
 //! [defmt]: https://github.com/knurling-rs/defmt/
 
@@ -93,5 +94,6 @@ mod print_defmt {
 
     pub fn print(info: &PanicInfo) {
         defmt::error!("{}", defmt::Display2Format(info));
+        defmt::flush();
     }
 }
@@ -0,0 +1,224 @@
+//! Compile-time log filtering with the `DEFMT_LOG` environment variable
+//!
+//! The syntax follows `RUST_LOG`: a comma separated list of directives, e.g.
+//! `DEFMT_LOG=warn,app=info,app::radio=trace`. Each directive is one of
+//!
+//! - `level`: the level for all crates
+//! - `path`: everything (TRACE and up) in the crate / module at `path` (and its submodules)
+//! - `path=level`: `level` and up in the crate / module at `path` (and its submodules)
+//!
+//! where `level` is one of `trace`, `debug`, `info`, `warn`, `error` or `off`. The directive with
+//! the longest matching path applies to a log statement; if no directive applies the log statement
+//! is compiled out.
+
+use std::env;
+
+use defmt_parser::Level;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+
+pub(crate) struct EnvFilter {
+    directives: Vec<Directive>,
+}
+
+/// Whether a log statement is enabled by the filter
+pub(crate) enum Enabled {
+    /// The same in every module of the crate
+    Always(bool),
+    /// Depends on the module; a const expression that uses `module_path!`
+    ByModule(TokenStream2),
+}
+
+#[derive(Debug, PartialEq)]
+struct Directive {
+    /// `None` applies to all crates
+    path: Option<String>,
+    /// Minimum level that's logged; `None` means nothing is logged
+    level: Option<Level>,
+}
+
+impl EnvFilter {
+    /// Returns `None` if `DEFMT_LOG` is not set
+    pub(crate) fn from_env() -> Option<Result<Self, String>> {
+        // NOTE the expansion of the log macros uses `option_env!("DEFMT_LOG")` so that crates are
+        // rebuilt when it changes
+        let filter = env::var("DEFMT_LOG").ok()?;
+        Some(Self::parse(&filter).map_err(|e| format!("invalid `DEFMT_LOG` value: {}", e)))
+    }
+
+    fn parse(filter: &str) -> Result<Self, String> {
+        let mut directives: Vec<Directive> = vec![];
+        for directive in filter.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (path, level) = match directive.find('=') {
+                Some(pos) => {
+                    let level = &directive[pos + 1..];
+                    let level = parse_level(level).ok_or_else(|| {
+                        format!("unknown log level `{}` in `{}`", level, directive)
+                    })?;
+                    (Some(parse_path(&directive[..pos])?), level)
+                }
+                None => match parse_level(directive) {
+                    Some(level) => (None, level),
+                    None => (Some(parse_path(directive)?), Some(Level::Trace)),
+                },
+            };
+
+            // later directives override earlier ones
+            directives.retain(|directive| directive.path != path);
+            directives.push(Directive { path, level });
+        }
+
+        Ok(Self { directives })
+    }
+
+    /// Returns whether a log statement at `level` in the current module of `krate` is enabled
+    pub(crate) fn is_enabled(&self, level: Level, krate: &str) -> Enabled {
+        let enabled = |directive: &Directive| match directive.level {
+            Some(min_level) => level >= min_level,
+            None => false,
+        };
+
+        let default = match self.directives.iter().find(|d| d.path.is_none()) {
+            Some(directive) => enabled(directive),
+            None => false,
+        };
+
+        let mut modules = self
+            .directives
+            .iter()
+            .filter_map(|directive| {
+                let path = directive.path.as_deref()?;
+                if path.split("::").next() == Some(krate) {
+                    Some((path, enabled(directive)))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // most specific path first
+        modules.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+
+        if modules.iter().all(|(_, enabled)| *enabled == default) {
+            return Enabled::Always(default);
+        }
+
+        let (paths, enabled): (Vec<_>, Vec<_>) = modules.into_iter().unzip();
+        Enabled::ByModule(quote!(
+            #(if defmt::export::path_matches(module_path!(), #paths) { #enabled } else)*
+            { #default }
+        ))
+    }
+}
+
+fn parse_level(level: &str) -> Option<Option<Level>> {
+    Some(Some(match level {
+        "trace" => Level::Trace,
+        "debug" => Level::Debug,
+        "info" => Level::Info,
+        "warn" => Level::Warn,
+        "error" => Level::Error,
+        "off" => return Some(None),
+        _ => return None,
+    }))
+}
+
+fn parse_path(path: &str) -> Result<String, String> {
+    let is_valid = !path.is_empty()
+        && path.split("::").all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        });
+
+    if !is_valid {
+        return Err(format!("`{}` is not a module path", path));
+    }
+
+    // crate names may contain dashes but `module_path!` uses underscores
+    Ok(path.replace('-', "_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directive(path: Option<&str>, level: Option<Level>) -> Directive {
+        Directive {
+            path: path.map(str::to_string),
+            level,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let filter = EnvFilter::parse("warn, app=info,app::radio,my-driver=off").unwrap();
+        assert_eq!(
+            filter.directives,
+            [
+                directive(None, Some(Level::Warn)),
+                directive(Some("app"), Some(Level::Info)),
+                directive(Some("app::radio"), Some(Level::Trace)),
+                directive(Some("my_driver"), None),
+            ]
+        );
+
+        assert!(EnvFilter::parse("").unwrap().directives.is_empty());
+    }
+
+    #[test]
+    fn later_directives_override() {
+        let filter = EnvFilter::parse("app=info,debug,app=error").unwrap();
+        assert_eq!(
+            filter.directives,
+            [
+                directive(None, Some(Level::Debug)),
+                directive(Some("app"), Some(Level::Error)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(EnvFilter::parse("app=verbose").is_err());
+        assert!(EnvFilter::parse("app::=info").is_err());
+        assert!(EnvFilter::parse("=info").is_err());
+        assert!(EnvFilter::parse("app radio").is_err());
+    }
+
+    #[test]
+    fn is_enabled() {
+        let filter = EnvFilter::parse("warn,app=info,app::radio=trace").unwrap();
+
+        // the crate is not mentioned; only the global level applies
+        assert!(matches!(
+            filter.is_enabled(Level::Warn, "other"),
+            Enabled::Always(true)
+        ));
+        assert!(matches!(
+            filter.is_enabled(Level::Info, "other"),
+            Enabled::Always(false)
+        ));
+
+        // every module of `app` logs errors
+        assert!(matches!(
+            filter.is_enabled(Level::Error, "app"),
+            Enabled::Always(true)
+        ));
+
+        let expected = quote!(
+            if defmt::export::path_matches(module_path!(), "app::radio") {
+                true
+            } else if defmt::export::path_matches(module_path!(), "app") {
+                false
+            } else {
+                false
+            }
+        );
+        match filter.is_enabled(Level::Debug, "app") {
+            Enabled::ByModule(enabled) => assert_eq!(enabled.to_string(), expected.to_string()),
+            Enabled::Always(_) => panic!("the modules of `app` have different levels"),
+        }
+    }
+}
@@ -2,27 +2,31 @@
 
 #![doc(html_logo_url = "https://knurling.ferrous-systems.com/knurling_logo_light_text.svg")]
 
+mod env_filter;
 mod symbol;
 
 use std::{
     collections::hash_map::DefaultHasher,
     convert::TryFrom,
+    env,
     fmt::Write as _,
     hash::{Hash, Hasher},
+    ops::Range,
 };
 
 use defmt_parser::{Fragment, Level, ParserMode};
+use env_filter::{Enabled, EnvFilter};
 use proc_macro::TokenStream;
-use proc_macro2::{Ident as Ident2, Span as Span2, TokenStream as TokenStream2};
+use proc_macro2::{Ident as Ident2, Span as Span2, TokenStream as TokenStream2, TokenTree};
 use quote::{format_ident, quote};
 use syn::{
     parse::{self, Parse, ParseStream},
     parse_macro_input,
     punctuated::Punctuated,
     spanned::Spanned as _,
-    Attribute, Data, DeriveInput, Expr, ExprPath, Fields, FieldsNamed, FieldsUnnamed, GenericParam,
-    ItemFn, ItemStruct, LitStr, Path, PathArguments, PathSegment, ReturnType, Token, Type,
-    WhereClause, WherePredicate,
+    Attribute, AttributeArgs, Data, DeriveInput, Expr, ExprAssign, ExprLit, ExprMacro, ExprPath,
+    Fields, FnArg, GenericParam, ItemFn, ItemStruct, Lit, LitStr, Meta, NestedMeta, Pat, Path,
+    PathArguments, PathSegment, ReturnType, Token, Type, WhereClause, WherePredicate,
 };
 
 /// Checks if any attribute in `attrs_to_check` is in `reject_list` and returns a compiler error if there's a match
@@ -90,6 +94,60 @@ pub fn global_logger(args: TokenStream, input: TokenStream) -> TokenStream {
         unsafe fn _defmt_write(bytes: &[u8])  {
             <#ident as defmt::Logger>::write(bytes)
         }
+
+        #[no_mangle]
+        fn _defmt_flush()  {
+            <#ident as defmt::Logger>::flush()
+        }
+    )
+    .into()
+}
+
+struct SinkArg {
+    ty: Type,
+    _arrow: Token![=>],
+    max_level: Expr,
+}
+
+impl Parse for SinkArg {
+    fn parse(input: ParseStream) -> parse::Result<Self> {
+        Ok(Self {
+            ty: input.parse()?,
+            _arrow: input.parse()?,
+            max_level: input.parse()?,
+        })
+    }
+}
+
+#[proc_macro]
+pub fn sinks(ts: TokenStream) -> TokenStream {
+    let sinks = parse_macro_input!(ts with Punctuated::<SinkArg, Token![,]>::parse_terminated);
+    if sinks.is_empty() {
+        return parse::Error::new(Span2::call_site(), "expected at least one sink")
+            .to_compile_error()
+            .into();
+    }
+
+    let tys = sinks.iter().map(|sink| &sink.ty).collect::<Vec<_>>();
+    let max_levels = sinks.iter().map(|sink| &sink.max_level).collect::<Vec<_>>();
+    quote!(
+        #[no_mangle]
+        unsafe fn _defmt_sinks_write(level: u8, bytes: &[u8]) {
+            #(
+                if level <= (#max_levels) as u8 {
+                    <#tys as defmt::Sink>::write(bytes)
+                }
+            )*
+        }
+
+        #[no_mangle]
+        unsafe fn _defmt_sinks_end_frame(level: u8) {
+            #(
+                if level <= (#max_levels) as u8 {
+                    <#tys as defmt::Sink>::end_frame()
+                }
+            )*
+        }
     )
     .into()
 }
@@ -150,20 +208,16 @@ pub fn timestamp(ts: TokenStream) -> TokenStream {
     let ls = f.litstr.value();
 
     let symname = Ident2::new("S", Span2::call_site());
-    let sym = mkstatic(symname.clone(), &ls, "timestamp");
+    let sym = mkstatic(symname.clone(), &ls, "timestamp", &[]);
 
-    let fragments = match defmt_parser::parse(&ls, ParserMode::Strict) {
+    let (fragments, arg_names) = match defmt_parser::parse_with_names(&ls, ParserMode::Strict) {
+        Ok(parsed) => parsed,
+        Err(e) => return format_string_error(&f.litstr, e).to_compile_error().into(),
+    };
+    let args = match format_string_args(&arg_names, f.rest, f.litstr.span()) {
         Ok(args) => args,
-        Err(e) => {
-            return parse::Error::new(f.litstr.span(), e)
-                .to_compile_error()
-                .into()
-        }
+        Err(e) => return e.to_compile_error().into(),
     };
-    let args: Vec<_> = f
-        .rest
-        .map(|(_, exprs)| exprs.into_iter().collect())
-        .unwrap_or_default();
     let (pats, exprs) = match Codegen::new(&fragments, args.len(), f.litstr.span()) {
         Ok(cg) => (cg.pats, cg.exprs),
         Err(e) => return e.to_compile_error().into(),
@@ -243,10 +297,17 @@ fn necessary_features_for_level(level: Level, debug_assertions: bool) -> &'stati
 }
 
 // `#[derive(Format)]`
-#[proc_macro_derive(Format)]
+#[proc_macro_derive(Format, attributes(defmt))]
 pub fn format(ts: TokenStream) -> TokenStream {
-    let mut input = parse_macro_input!(ts as DeriveInput);
+    let input = parse_macro_input!(ts as DeriveInput);
+    derive_format(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn derive_format(mut input: DeriveInput) -> parse::Result<TokenStream2> {
     let span = input.span();
+    let container = ContainerAttrs::parse(&input.attrs)?;
 
     let ident = input.ident;
     let mut fs = String::new();
@@ -254,6 +315,13 @@ pub fn format(ts: TokenStream) -> TokenStream {
     let mut exprs = vec![];
     match input.data {
         Data::Enum(de) => {
+            if let Some(span) = container.struct_only_span() {
+                return Err(parse::Error::new(
+                    span,
+                    "`#[defmt(format)]` and `#[defmt(transparent)]` can only be used on structs",
+                ));
+            }
+
             if de.variants.is_empty() {
                 // For zero-variant enums, this is unreachable code.
                 exprs.push(quote!(match *self {}));
@@ -262,6 +330,12 @@ pub fn format(ts: TokenStream) -> TokenStream {
                 let mut first = true;
                 for (i, var) in de.variants.iter().enumerate() {
                     let vident = &var.ident;
+                    if let Some(attr) = var.attrs.iter().find(|attr| attr.path.is_ident("defmt")) {
+                        return Err(parse::Error::new(
+                            attr.span(),
+                            "`#[defmt(..)]` attributes are not supported on enum variants",
+                        ));
+                    }
 
                     if first {
                         first = false;
@@ -271,8 +345,8 @@ pub fn format(ts: TokenStream) -> TokenStream {
                     fs.push_str(&vident.to_string());
 
                     let mut pats = vec![];
-                    let exprs = fields(&var.fields, &mut fs, &mut field_types, &mut pats);
-                    let pats = quote!( { #(#pats),* } );
+                    let exprs = fields(&var.fields, &mut fs, &mut field_types, &mut pats)?;
+                    let pats = quote!( { #(#pats,)* .. } );
 
                     let len = de.variants.len();
                     let encode_discriminant = if len == 1 {
@@ -296,12 +370,10 @@ pub fn format(ts: TokenStream) -> TokenStream {
                         )
                     } else {
                         // u128 case is omitted with the assumption, that usize is never greater than u64
-                        return parse::Error::new(
+                        return Err(parse::Error::new(
                             span,
                             format!("`#[derive(Format)]` does not support enums with more than {} variants", u64::MAX),
-                        )
-                        .to_compile_error()
-                        .into();
+                        ));
                     };
 
                     arms.push(quote!(
@@ -332,27 +404,78 @@ pub fn format(ts: TokenStream) -> TokenStream {
         }
 
         Data::Struct(ds) => {
-            fs = ident.to_string();
-            let mut pats = vec![];
-            let args = fields(&ds.fields, &mut fs, &mut field_types, &mut pats);
-
-            let sym = mksym(&fs, "derived", false);
-            exprs.push(quote!(
-                if f.inner.needs_tag() {
-                    f.inner.istr(&defmt::export::istr(#sym));
-                }
-            ));
-            exprs.push(quote!(match self {
-                Self { #(#pats),* } => {
-                    #(#args;)*
+            if let Some(format) = &container.format {
+                // `#[defmt(format = "..")]`: the fields are the arguments of the format string
+                let fields = formatted_fields(&ds.fields)?;
+                let pats = fields.iter().map(|field| &field.pat);
+                let mut args = vec![];
+                for field in &fields {
+                    let binding = &field.binding;
+                    args.push(match field.attrs.adapter {
+                        Some(Adapter::Debug) => quote!(defmt::Debug2Format(#binding)),
+                        Some(Adapter::Display) => quote!(defmt::Display2Format(#binding)),
+                        None => quote!(#binding),
+                    });
+                    if let Some(hint) = &field.attrs.hint {
+                        return Err(parse::Error::new(
+                            hint.span(),
+                            "`#[defmt(hint)]` cannot be used together with `#[defmt(format)]`; \
+                             put the display hint in the format string instead",
+                        ));
+                    }
                 }
-            }));
+                exprs.push(quote!(match self {
+                    Self { #(#pats,)* .. } => defmt::write!(f, #format #(, #args)*),
+                }));
+            } else if let Some(transparent) = container.transparent {
+                // `#[defmt(transparent)]`: format the struct like its only field
+                let fields = formatted_fields(&ds.fields)?;
+                let field = match &*fields {
+                    [field] if field.attrs.adapter.is_none() && field.attrs.hint.is_none() => field,
+                    [_] => {
+                        return Err(parse::Error::new(
+                            transparent,
+                            "the field of a `#[defmt(transparent)]` struct cannot have \
+                             `#[defmt(..)]` attributes other than `skip`",
+                        ))
+                    }
+                    _ => {
+                        return Err(parse::Error::new(
+                            transparent,
+                            "`#[defmt(transparent)]` requires a struct with exactly one \
+                             (non-skipped) field",
+                        ))
+                    }
+                };
+                let pat = &field.pat;
+                let binding = &field.binding;
+                exprs.push(quote!(match self {
+                    Self { #pat, .. } => defmt::Format::format(#binding, f),
+                }));
+            } else {
+                fs = ident.to_string();
+                let mut pats = vec![];
+                let args = fields(&ds.fields, &mut fs, &mut field_types, &mut pats)?;
+
+                let sym = mksym(&fs, "derived", false);
+                exprs.push(quote!(
+                    if f.inner.needs_tag() {
+                        f.inner.istr(&defmt::export::istr(#sym));
+                    }
+                ));
+                exprs.push(quote!(match self {
+                    Self { #(#pats,)* .. } => {
+                        #(#args;)*
+                    }
+                }));
+            }
         }
 
         Data::Union(..) => {
-            return parse::Error::new(span, "`#[derive(Format)]` does not support unions")
-                .to_compile_error()
-                .into();
+            return Err(parse::Error::new(
+                span,
+                "`#[derive(Format)]` does not support unions",
+            ));
         }
     }
 
@@ -360,99 +483,275 @@ pub fn format(ts: TokenStream) -> TokenStream {
     let mut where_clause: WhereClause = where_clause.clone();
     let (impl_generics, type_generics, _) = input.generics.split_for_impl();
 
-    // Extend where-clause with `Format` bounds for type parameters.
-    for param in &input.generics.params {
-        if let GenericParam::Type(ty) = param {
-            let ident = &ty.ident;
-            where_clause
-                .predicates
-                .push(syn::parse::<WherePredicate>(quote!(#ident: defmt::Format).into()).unwrap());
+    match container.bound {
+        // `#[defmt(bound = "..")]` replaces the bounds on the type parameters
+        Some(bound) => where_clause.predicates.extend(bound),
+        None => {
+            // Extend where-clause with `Format` bounds for type parameters.
+            for param in &input.generics.params {
+                if let GenericParam::Type(ty) = param {
+                    let ident = &ty.ident;
+                    where_clause.predicates.push(
+                        syn::parse::<WherePredicate>(quote!(#ident: defmt::Format).into()).unwrap(),
+                    );
+                }
+            }
         }
     }
 
-    quote!(
+    Ok(quote!(
         impl #impl_generics defmt::Format for #ident #type_generics #where_clause {
             fn format(&self, f: defmt::Formatter) {
                 #(#exprs)*
             }
         }
-    )
-    .into()
+    ))
 }
 
-fn fields(
-    fields: &Fields,
-    format: &mut String,
-    // collect all *non-native* types that appear as fields
-    field_types: &mut Vec<Type>,
-    pats: &mut Vec<TokenStream2>,
-) -> Vec<TokenStream2> {
-    let mut list = vec![];
-    match fields {
-        Fields::Named(FieldsNamed { named: fs, .. })
-        | Fields::Unnamed(FieldsUnnamed { unnamed: fs, .. }) => {
-            let named = match fields {
-                Fields::Named(..) => true,
-                Fields::Unnamed(..) => false,
-                _ => unreachable!(),
-            };
+/// Returns the items of the `#[defmt(..)]` attributes in `attrs`
+fn defmt_attrs(attrs: &[Attribute]) -> parse::Result<Vec<NestedMeta>> {
+    let mut items = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("defmt")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested),
+            meta => return Err(parse::Error::new(meta.span(), "expected `#[defmt(..)]`")),
+        }
+    }
+    Ok(items)
+}
+
+/// The `#[defmt(..)]` attributes of a `#[derive(Format)]` struct or enum
+#[derive(Default)]
+struct ContainerAttrs {
+    /// `format = ".."`
+    format: Option<LitStr>,
+    /// `transparent`; the span of the attribute
+    transparent: Option<Span2>,
+    /// `bound = ".."`
+    bound: Option<Punctuated<WherePredicate, Token![,]>>,
+}
 
-            if !fs.is_empty() {
-                if named {
-                    format.push_str(" {{ ");
-                } else {
-                    format.push('(');
+impl ContainerAttrs {
+    fn parse(attrs: &[Attribute]) -> parse::Result<Self> {
+        let mut parsed = Self::default();
+        for item in defmt_attrs(attrs)? {
+            match &item {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("format") => {
+                    parsed.format = Some(lit_str(&nv.lit)?.clone());
                 }
-                let mut first = true;
-                for (i, f) in fs.iter().enumerate() {
-                    if first {
-                        first = false;
-                    } else {
-                        format.push_str(", ");
-                    }
-                    let ty = as_native_type(&f.ty).unwrap_or_else(|| {
-                        field_types.push(f.ty.clone());
-                        "?".to_string()
-                    });
-                    if let Some(ident) = f.ident.as_ref() {
-                        core::write!(format, "{}: {{={}}}", ident, ty).ok();
-
-                        if ty == "?" {
-                            list.push(quote!(f.inner.fmt(#ident, false)));
-                        } else {
-                            let method = format_ident!("{}", ty);
-                            list.push(quote!(f.inner.#method(#ident)));
-                        }
-                        pats.push(quote!( #ident ));
-                    } else {
-                        // Unnamed (tuple) field.
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("transparent") => {
+                    parsed.transparent = Some(path.span());
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("bound") => {
+                    let bound = lit_str(&nv.lit)?;
+                    parsed.bound = Some(bound.parse_with(Punctuated::parse_terminated)?);
+                }
+                _ => {
+                    return Err(parse::Error::new(
+                        item.span(),
+                        "expected `format = \"..\"`, `transparent` or `bound = \"..\"`",
+                    ))
+                }
+            }
+        }
+        if let (Some(format), Some(_)) = (&parsed.format, parsed.transparent) {
+            return Err(parse::Error::new(
+                format.span(),
+                "`#[defmt(format)]` cannot be used together with `#[defmt(transparent)]`",
+            ));
+        }
+        Ok(parsed)
+    }
 
-                        core::write!(format, "{{={}}}", ty).ok();
+    /// Returns the span of the first attribute that only applies to structs
+    fn struct_only_span(&self) -> Option<Span2> {
+        self.format
+            .as_ref()
+            .map(|format| format.span())
+            .or(self.transparent)
+    }
+}
 
-                        let ident = format_ident!("arg{}", i);
-                        if ty == "?" {
-                            list.push(quote!(f.inner.fmt(#ident, false)));
-                        } else {
-                            let method = format_ident!("{}", ty);
-                            list.push(quote!(f.inner.#method(#ident)));
-                        }
+/// The `#[defmt(..)]` attributes of a field
+#[derive(Default)]
+struct FieldAttrs {
+    /// `skip`: the field is not formatted
+    skip: bool,
+    /// `Debug2Format` or `Display2Format`
+    adapter: Option<Adapter>,
+    /// `hint = ".."`
+    hint: Option<LitStr>,
+}
 
-                        let i = syn::Index::from(i);
-                        pats.push(quote!( #i: #ident ));
+/// A `core::fmt` trait used to format a field, instead of `Format`
+#[derive(Clone, Copy, PartialEq)]
+enum Adapter {
+    Debug,
+    Display,
+}
+
+impl FieldAttrs {
+    fn parse(attrs: &[Attribute]) -> parse::Result<Self> {
+        let mut parsed = Self::default();
+        let mut skip = None;
+        for item in defmt_attrs(attrs)? {
+            match &item {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                    parsed.skip = true;
+                    skip = Some(path.span());
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("Debug2Format") => {
+                    parsed.adapter = Some(Adapter::Debug);
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("Display2Format") => {
+                    parsed.adapter = Some(Adapter::Display);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("hint") => {
+                    let hint = lit_str(&nv.lit)?;
+                    // the type doesn't matter, only the hint is checked
+                    if let Err(e) =
+                        defmt_parser::parse(&format!("{{=?:{}}}", hint.value()), ParserMode::Strict)
+                    {
+                        return Err(parse::Error::new(hint.span(), e));
                     }
+                    parsed.hint = Some(hint.clone());
                 }
-                if named {
-                    format.push_str(" }}");
-                } else {
-                    format.push(')');
+                _ => {
+                    return Err(parse::Error::new(
+                        item.span(),
+                        "expected `skip`, `Debug2Format`, `Display2Format` or `hint = \"..\"`",
+                    ))
                 }
             }
         }
+        if let (Some(skip), true) = (skip, parsed.adapter.is_some() || parsed.hint.is_some()) {
+            return Err(parse::Error::new(
+                skip,
+                "a `#[defmt(skip)]` field cannot have other `#[defmt(..)]` attributes",
+            ));
+        }
+        if let (Some(_), Some(hint)) = (parsed.adapter, &parsed.hint) {
+            return Err(parse::Error::new(
+                hint.span(),
+                "`#[defmt(hint)]` has no effect on fields formatted with `Debug2Format` or \
+                 `Display2Format`",
+            ));
+        }
+        Ok(parsed)
+    }
+}
+
+fn lit_str(lit: &Lit) -> parse::Result<&LitStr> {
+    match lit {
+        Lit::Str(s) => Ok(s),
+        _ => Err(parse::Error::new(lit.span(), "expected a string literal")),
+    }
+}
+
+/// A field that is not `#[defmt(skip)]`ped
+struct FormattedField<'a> {
+    field: &'a syn::Field,
+    attrs: FieldAttrs,
+    /// Variable the pattern `pat` binds the field to
+    binding: Ident2,
+    pat: TokenStream2,
+}
+
+fn formatted_fields(fields: &Fields) -> parse::Result<Vec<FormattedField<'_>>> {
+    let mut formatted = vec![];
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = FieldAttrs::parse(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+
+        let (binding, pat) = match &field.ident {
+            Some(ident) => (ident.clone(), quote!( #ident )),
+            // Unnamed (tuple) field.
+            None => {
+                let binding = format_ident!("arg{}", i);
+                let i = syn::Index::from(i);
+                (binding.clone(), quote!( #i: #binding ))
+            }
+        };
+        formatted.push(FormattedField {
+            field,
+            attrs,
+            binding,
+            pat,
+        });
+    }
+    Ok(formatted)
+}
+
+fn fields(
+    fields: &Fields,
+    format: &mut String,
+    // collect all *non-native* types that appear as fields
+    field_types: &mut Vec<Type>,
+    pats: &mut Vec<TokenStream2>,
+) -> parse::Result<Vec<TokenStream2>> {
+    let named = matches!(fields, Fields::Named(..));
+    // skipped fields are neither part of the format string nor bound by the pattern
+    let fields = formatted_fields(fields)?;
+
+    let mut list = vec![];
+    if !fields.is_empty() {
+        if named {
+            format.push_str(" {{ ");
+        } else {
+            format.push('(');
+        }
+        let mut first = true;
+        for FormattedField {
+            field: f,
+            attrs,
+            binding: ident,
+            pat,
+        } in fields
+        {
+            if first {
+                first = false;
+            } else {
+                format.push_str(", ");
+            }
+            let (ty, method) = match attrs.adapter {
+                Some(Adapter::Debug) => ("__internal_Debug".to_string(), Some("debug")),
+                Some(Adapter::Display) => ("__internal_Display".to_string(), Some("display")),
+                None => match as_native_type(&f.ty) {
+                    Some(ty) => (ty, None),
+                    None => {
+                        field_types.push(f.ty.clone());
+                        ("?".to_string(), None)
+                    }
+                },
+            };
+            let param = match &attrs.hint {
+                Some(hint) => format!("{{={}:{}}}", ty, hint.value()),
+                None => format!("{{={}}}", ty),
+            };
+            if named {
+                core::write!(format, "{}: {}", ident, param).ok();
+            } else {
+                format.push_str(&param);
+            }
 
-        Fields::Unit => {}
+            if ty == "?" {
+                list.push(quote!(f.inner.fmt(#ident, false)));
+            } else {
+                let method = format_ident!("{}", method.unwrap_or(&ty));
+                list.push(quote!(f.inner.#method(#ident)));
+            }
+            pats.push(pat);
+        }
+        if named {
+            format.push_str(" }}");
+        } else {
+            format.push(')');
+        }
     }
 
-    list
+    Ok(list)
 }
 
 /// Returns `true` if `ty_name` refers to a builtin Rust type that has native support from defmt
@@ -492,47 +791,155 @@ fn cfg_if_logging_enabled(level: Level) -> TokenStream2 {
     )
 }
 
+/// Returns the `defmt::LevelFilter` discriminant that lets `level` through
+fn level_filter(level: Level) -> u8 {
+    // NOTE must match `defmt::LevelFilter`
+    match level {
+        Level::Error => 1,
+        Level::Warn => 2,
+        Level::Info => 3,
+        Level::Debug => 4,
+        Level::Trace => 5,
+    }
+}
+
+/// Wraps `log_stmt` in a check of the runtime filter (`runtime-filter` feature)
+///
+/// The check happens before the arguments of the log statement are evaluated.
+fn if_runtime_filter_enabled(level: Level, log_stmt: TokenStream2) -> TokenStream2 {
+    if cfg!(feature = "runtime-filter") {
+        let level = level_filter(level);
+        quote!(
+            const _DEFMT_MODULE_: defmt::export::ModulePath =
+                defmt::export::ModulePath::new(module_path!());
+            if defmt::export::enabled(#level, &_DEFMT_MODULE_) {
+                #log_stmt
+            }
+        )
+    } else {
+        log_stmt
+    }
+}
+
 fn log_ts(level: Level, ts: TokenStream) -> TokenStream {
     log(level, parse_macro_input!(ts as FormatArgs)).into()
 }
 
 fn log(level: Level, log: FormatArgs) -> TokenStream2 {
     let ls = log.litstr.value();
-    let fragments = match defmt_parser::parse(&ls, ParserMode::Strict) {
-        Ok(args) => args,
-        Err(e) => return parse::Error::new(log.litstr.span(), e).to_compile_error(),
+    let (fragments, arg_names) = match defmt_parser::parse_with_names(&ls, ParserMode::Strict) {
+        Ok(parsed) => parsed,
+        Err(e) => return format_string_error(&log.litstr, e).to_compile_error(),
     };
 
-    let args = log
-        .rest
-        .map(|(_, exprs)| exprs.into_iter().collect())
-        .unwrap_or_else(Vec::new);
+    let (mut args, fields) = match split_fields(log.rest.into_iter().flat_map(|(_, exprs)| exprs)) {
+        Ok(split) => split,
+        Err(e) => return e.to_compile_error(),
+    };
+    // `name = value` arguments that the format string doesn't use are key-value fields
+    let fields = bind_named_args(&arg_names, &mut args, fields, log.litstr.span());
+    if let Err(e) = check_misspelled_fields(&fields, &arg_names) {
+        return e.to_compile_error();
+    }
 
-    let (pats, exprs) = match Codegen::new(&fragments, args.len(), log.litstr.span()) {
+    let (mut pats, mut exprs) = match Codegen::new(&fragments, args.len(), log.litstr.span()) {
         Ok(cg) => (cg.pats, cg.exprs),
         Err(e) => return e.to_compile_error(),
     };
 
-    let sym = mksym(&ls, level.as_str(), true);
-    let logging_enabled = cfg_if_logging_enabled(level);
-    quote!({
-        #[cfg(#logging_enabled)] {
+    // fields are matched after the arguments and encoded like `{=?}` arguments
+    let mut names = vec![];
+    for (i, (name, value)) in fields.into_iter().enumerate() {
+        let pat = format_ident!("field{}", i);
+        exprs.push(quote!(_fmt_.fmt(#pat, false)));
+        pats.push(pat);
+        args.push(value);
+        names.push(name.to_string());
+    }
+
+    let sym = mksym_with_fields(&ls, level.as_str(), true, &names);
+    let set_level = if cfg!(feature = "sinks") {
+        let level = level_filter(level);
+        quote!(_fmt_.level(#level);)
+    } else {
+        quote!()
+    };
+    let log_stmt = if_runtime_filter_enabled(
+        level,
+        quote!(
             match (#(&(#args)),*) {
                 (#(#pats),*) => {
                     defmt::export::acquire();
                     let mut _fmt_ = defmt::InternalFormatter::new();
+                    #set_level
                     _fmt_.header(&defmt::export::istr(#sym));
                     #(#exprs;)*
+                    _fmt_.finalize();
                     defmt::export::release()
                 }
             }
-        }
-        // if logging is disabled match args, so they are not unused
-        #[cfg(not(#logging_enabled))]
+        ),
+    );
+    filter_log_stmt(level, log.litstr.span(), log_stmt, &args)
+}
+
+/// Applies the `DEFMT_LOG` or `defmt-*` feature filter to `log_stmt`
+///
+/// `args` are the arguments of the log statement; they are still evaluated when the log statement
+/// is filtered out.
+fn filter_log_stmt(
+    level: Level,
+    span: Span2,
+    log_stmt: TokenStream2,
+    args: &[Expr],
+) -> TokenStream2 {
+    let logging_enabled = cfg_if_logging_enabled(level);
+    // makes rustc track `DEFMT_LOG` so crates get rebuilt when it changes
+    let track_env = quote!(
+        const _: Option<&str> = option_env!("DEFMT_LOG");
+    );
+    // if logging is disabled match args, so they are not unused
+    let match_args = quote!(
         match (#(&(#args)),*) {
             _ => {}
         }
-    })
+    );
+    match EnvFilter::from_env() {
+        // `DEFMT_LOG` takes precedence over the `defmt-*` features
+        Some(Ok(filter)) => {
+            let krate = env::var("CARGO_CRATE_NAME").unwrap_or_default();
+            match filter.is_enabled(level, &krate) {
+                Enabled::Always(true) => quote!({
+                    #track_env
+                    #log_stmt
+                }),
+                // leaves out the interned string (and build ID entry) of the log statement
+                Enabled::Always(false) => quote!({
+                    #track_env
+                    #match_args
+                }),
+                // the interned string is kept but dead code elimination removes the rest
+                Enabled::ByModule(enabled) => quote!({
+                    #track_env
+                    const _DEFMT_LOG_ENABLED_: bool = #enabled;
+                    if _DEFMT_LOG_ENABLED_ {
+                        #log_stmt
+                    } else {
+                        #match_args
+                    }
+                }),
+            }
+        }
+        Some(Err(e)) => parse::Error::new(span, e).to_compile_error(),
+        None => quote!({
+            #track_env
+            #[cfg(#logging_enabled)] {
+                #log_stmt
+            }
+            #[cfg(not(#logging_enabled))]
+            #match_args
+        }),
+    }
 }
 
 struct DbgArgs {
@@ -547,6 +954,220 @@ impl Parse for DbgArgs {
     }
 }
 
+#[proc_macro]
+pub fn span(ts: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(ts as LitStr);
+    // the name is interned as a format string without parameters
+    let name = lit.value().replace('{', "{{").replace('}', "}}");
+
+    // spans are logged at the TRACE level
+    let level = Level::Trace;
+    let sym = mksym(&name, "span", true);
+    let level_filter = level_filter(level);
+    let enter = if_runtime_filter_enabled(
+        level,
+        quote!(
+            _span_ = defmt::Span::enter(defmt::export::istr(#sym), #level_filter);
+        ),
+    );
+    let enter = filter_log_stmt(level, lit.span(), enter, &[]);
+    // span IDs are handed out with a read-modify-write operation; without one the logger must
+    // not be preempted mid-frame (see the `atomic` module of `defmt`)
+    let check = if cfg!(feature = "preemptible-logger") {
+        quote!(
+            #[cfg(not(target_has_atomic = "16"))]
+            compile_error!(
+                "`span!` can't be used with a global logger that can be preempted mid-frame \
+                 (`preemptible-logger` feature) on targets without atomic read-modify-write \
+                 operations"
+            );
+        )
+    } else {
+        quote!()
+    };
+    quote!({
+        #check
+        let mut _span_ = defmt::Span::disabled();
+        #enter
+        _span_
+    })
+    .into()
+}
+
+/// Arguments of `#[instrument]`
+struct InstrumentArgs {
+    level: Level,
+    /// The function arguments to log on entry
+    args: Vec<Ident2>,
+    /// Whether to log the return value
+    ret: bool,
+}
+
+impl InstrumentArgs {
+    fn parse(args: AttributeArgs) -> parse::Result<Self> {
+        let mut parsed = Self {
+            level: Level::Trace,
+            args: vec![],
+            ret: false,
+        };
+        for arg in args {
+            match arg {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("level") => {
+                    let level = match &nv.lit {
+                        Lit::Str(level) => level,
+                        lit => return Err(parse::Error::new(lit.span(), "expected a string")),
+                    };
+                    parsed.level = match &*level.value() {
+                        "trace" => Level::Trace,
+                        "debug" => Level::Debug,
+                        "info" => Level::Info,
+                        "warn" => Level::Warn,
+                        "error" => Level::Error,
+                        _ => return Err(parse::Error::new(
+                            level.span(),
+                            "expected one of \"trace\", \"debug\", \"info\", \"warn\" or \"error\"",
+                        )),
+                    };
+                }
+                NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("args") => {
+                    for arg in list.nested {
+                        let ident = match &arg {
+                            NestedMeta::Meta(Meta::Path(path)) => path.get_ident().cloned(),
+                            _ => None,
+                        };
+                        parsed.args.push(ident.ok_or_else(|| {
+                            parse::Error::new(arg.span(), "expected the name of an argument")
+                        })?);
+                    }
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("ret") => parsed.ret = true,
+                _ => {
+                    return Err(parse::Error::new(
+                        arg.span(),
+                        "expected `level = \"..\"`, `args(..)` or `ret`",
+                    ))
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+#[proc_macro_attribute]
+pub fn instrument(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = match InstrumentArgs::parse(parse_macro_input!(args as AttributeArgs)) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let f = parse_macro_input!(input as ItemFn);
+
+    match instrument_fn(args, f) {
+        Ok(ts) => ts,
+        Err(e) => e.to_compile_error(),
+    }
+    .into()
+}
+
+fn instrument_fn(args: InstrumentArgs, f: ItemFn) -> parse::Result<TokenStream2> {
+    let sig = &f.sig;
+    if let Some(asyncness) = sig.asyncness {
+        return Err(parse::Error::new(
+            asyncness.span(),
+            "`#[instrument]` doesn't support `async` functions",
+        ));
+    }
+    if let Some(constness) = sig.constness {
+        return Err(parse::Error::new(
+            constness.span(),
+            "`#[instrument]` doesn't support `const` functions",
+        ));
+    }
+    let closure_output = match &sig.output {
+        ReturnType::Default => quote!(),
+        ReturnType::Type(_, ty) if contains_impl(quote!(#ty)) => {
+            return Err(parse::Error::new(
+                ty.span(),
+                "`#[instrument]` doesn't support functions that return `impl Trait`",
+            ))
+        }
+        output => quote!(#output),
+    };
+
+    let names = sig
+        .inputs
+        .iter()
+        .filter_map(|input| match input {
+            FnArg::Receiver(_) => Some("self".to_string()),
+            FnArg::Typed(pat) => match &*pat.pat {
+                Pat::Ident(pat) => Some(pat.ident.to_string()),
+                _ => None,
+            },
+        })
+        .collect::<Vec<_>>();
+    for arg in &args.args {
+        if !names.contains(&arg.to_string()) {
+            return Err(parse::Error::new(
+                arg.span(),
+                format!("`{}` has no argument named `{}`", sig.ident, arg),
+            ));
+        }
+    }
+
+    let level = format_ident!("{}", args.level.as_str());
+    let name = sig.ident.to_string();
+    let params = args
+        .args
+        .iter()
+        .map(|arg| format!("{}={{}}", arg))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let enter_format = format!("{}({})", name, params);
+    let enter_args = &args.args;
+    let block = &f.block;
+    // the exit is logged on every return path
+    let body = if args.ret {
+        // the body is moved into a closure to get hold of the return value
+        let exit_format = format!("{} -> {{}}", name);
+        quote!(
+            let _defmt_ret_ = defmt::export::call_once(move || #closure_output #block);
+            defmt::#level!(#exit_format, _defmt_ret_);
+            _defmt_ret_
+        )
+    } else {
+        // a guard leaves the body as is
+        let exit_format = format!("{} returned", name);
+        quote!(
+            struct _DefmtExit;
+            impl Drop for _DefmtExit {
+                fn drop(&mut self) {
+                    defmt::#level!(#exit_format);
+                }
+            }
+            let _defmt_exit_ = _DefmtExit;
+            #block
+        )
+    };
+
+    let attrs = &f.attrs;
+    let vis = &f.vis;
+    Ok(quote!(
+        #(#attrs)*
+        #vis #sig {
+            defmt::#level!(#enter_format #(, #enter_args)*);
+            #body
+        }
+    ))
+}
+
+/// Returns `true` if the type `ty` contains an `impl Trait` type
+fn contains_impl(ty: TokenStream2) -> bool {
+    ty.into_iter().any(|tt| match tt {
+        TokenTree::Ident(ident) => ident == "impl",
+        TokenTree::Group(group) => contains_impl(group.stream()),
+        _ => false,
+    })
+}
+
 #[proc_macro]
 pub fn dbg(input: TokenStream) -> TokenStream {
     let inputs = parse_macro_input!(input as DbgArgs).exprs;
@@ -730,7 +1351,7 @@ fn assert_binop(ts: TokenStream, binop: BinOp) -> TokenStream {
     };
 
     for val in vals {
-        log_args.push(ident_expr(*val));
+        log_args.push(ident_expr(val, Span2::call_site()));
     }
 
     let log_stmt = match binop {
@@ -830,7 +1451,7 @@ pub fn unwrap(ts: TokenStream) -> TokenStream {
         log(Level::Error, FormatArgs { litstr, rest })
     } else {
         let mut log_args = Punctuated::new();
-        log_args.push(ident_expr("_unwrap_err"));
+        log_args.push(ident_expr("_unwrap_err", Span2::call_site()));
 
         let litstr = LitStr::new(
             &format!(
@@ -855,10 +1476,10 @@ pub fn unwrap(ts: TokenStream) -> TokenStream {
     .into()
 }
 
-fn ident_expr(name: &str) -> Expr {
+fn ident_expr(name: &str, span: Span2) -> Expr {
     let mut segments = Punctuated::new();
     segments.push(PathSegment {
-        ident: Ident2::new(name, Span2::call_site()),
+        ident: Ident2::new(name, span),
         arguments: PathArguments::None,
     });
 
@@ -957,10 +1578,238 @@ struct FormatArgs {
     rest: Option<(Token![,], Punctuated<Expr, Token![,]>)>,
 }
 
+/// A key-value field of a log statement: `key = value`
+type Field = (Ident2, Expr);
+
+/// Splits the arguments of a log statement into the format string arguments and the key-value
+/// fields (`key = value`) that follow them
+fn split_fields(exprs: impl IntoIterator<Item = Expr>) -> parse::Result<(Vec<Expr>, Vec<Field>)> {
+    let mut args = vec![];
+    let mut fields: Vec<Field> = vec![];
+    for expr in exprs {
+        match expr {
+            Expr::Assign(ExprAssign { left, right, .. }) => {
+                let name = match &*left {
+                    Expr::Path(ExprPath {
+                        path, qself: None, ..
+                    }) => path.get_ident().cloned(),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    parse::Error::new(left.span(), "field name must be an identifier")
+                })?;
+                if fields.iter().any(|(field, _)| *field == name) {
+                    return Err(parse::Error::new(
+                        name.span(),
+                        format!("field `{}` is specified more than once", name),
+                    ));
+                }
+                fields.push((name, *right));
+            }
+            expr if !fields.is_empty() => {
+                return Err(parse::Error::new(
+                    expr.span(),
+                    "format string arguments must come before the `key = value` fields",
+                ))
+            }
+            expr => args.push(expr),
+        }
+    }
+    Ok((args, fields))
+}
+
+/// Parses the format string of a macro: a string literal, or a `concat!` or `stringify!` call
+///
+/// A proc macro can't expand the macros in its input so `concat!` and `stringify!` are evaluated
+/// here, which lets wrapper macros build format strings like `concat!("[radio] ", $fmt)`. The
+/// result spans the whole expression.
+fn parse_format_string(input: ParseStream) -> parse::Result<LitStr> {
+    let expr = input.parse::<Expr>()?;
+    match strip_groups(&expr) {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(litstr),
+            ..
+        }) => Ok(litstr.clone()),
+        _ => {
+            let mut value = String::new();
+            eval_format_string(&expr, &mut value)?;
+            Ok(LitStr::new(&value, expr.span()))
+        }
+    }
+}
+
+/// Converts a format string error into a compile error that points at the offending part of
+/// `litstr`
+///
+/// rustc can only point into a string literal on nightly, and only if the literal is in the source
+/// code as is; otherwise the error points at the whole literal, and its message names the part.
+fn format_string_error(litstr: &LitStr, mut e: defmt_parser::Error) -> parse::Error {
+    if let Some(span) = format_string_span(litstr, &e.span) {
+        return parse::Error::new(span, e);
+    }
+
+    let format = litstr.value();
+    let location = match enclosing_parameter(&format, &e.span) {
+        Some(fragment) if !fragment.is_empty() => format!(" in `{}`", fragment),
+        _ => format!(" at byte {}", e.span.start),
+    };
+    e.message.to_mut().push_str(&location);
+    parse::Error::new(litstr.span(), e)
+}
+
+/// Returns the parameter (`{..}`) of `format` that `range` is part of or, if there's none, the
+/// text at `range`
+fn enclosing_parameter<'a>(format: &'a str, range: &Range<usize>) -> Option<&'a str> {
+    let fragment = format.get(range.clone())?;
+    let (before, after) = (&format[..range.start], &format[range.end..]);
+    let is_brace = |c| c == '{' || c == '}';
+    let start = match before.rfind(is_brace) {
+        Some(i) if !fragment.starts_with('{') && before[i..].starts_with('{') => i,
+        _ => range.start,
+    };
+    let end = match after.find(is_brace) {
+        Some(i) if !fragment.ends_with('}') && after[i..].starts_with('}') => range.end + i + 1,
+        _ => range.end,
+    };
+    Some(&format[start..end])
+}
+
+fn format_string_span(litstr: &LitStr, range: &Range<usize>) -> Option<Span2> {
+    let token = litstr.token();
+    let repr = token.to_string();
+    // `litstr` may have been built by `concat!` or from another format string
+    if litstr.span().source_text()? != repr {
+        return None;
+    }
+    // the byte offsets are only valid if the literal contains no escape sequences
+    let start = repr.find('"')? + 1;
+    let end = repr.rfind('"')?;
+    if start > end || repr[start..end] != litstr.value() {
+        return None;
+    }
+    token.subspan(start + range.start..start + range.end)
+}
+
+/// Strips the invisible groups that `macro_rules!` puts around `$fmt:expr` fragments
+fn strip_groups(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Group(group) => strip_groups(&group.expr),
+        expr => expr,
+    }
+}
+
+/// Appends the string that `expr` evaluates to, as `concat!` would, to `value`
+fn eval_format_string(expr: &Expr, value: &mut String) -> parse::Result<()> {
+    match strip_groups(expr) {
+        Expr::Lit(ExprLit { lit, .. }) => match lit {
+            Lit::Str(s) => value.push_str(&s.value()),
+            Lit::Char(c) => value.push(c.value()),
+            Lit::Int(i) => value.push_str(i.base10_digits()),
+            Lit::Float(f) => value.push_str(f.base10_digits()),
+            Lit::Bool(b) => value.push_str(if b.value { "true" } else { "false" }),
+            _ => {
+                return Err(parse::Error::new(
+                    lit.span(),
+                    "byte string literals are not valid in a format string",
+                ))
+            }
+        },
+        // `concat!`, `core::concat!`, `std::concat!`, ..
+        Expr::Macro(ExprMacro { mac, .. })
+            if macro_name(&mac.path).as_deref() == Some("concat") =>
+        {
+            let args = mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)?;
+            for arg in &args {
+                eval_format_string(arg, value)?;
+            }
+        }
+        Expr::Macro(ExprMacro { mac, .. })
+            if macro_name(&mac.path).as_deref() == Some("stringify") =>
+        {
+            value.push_str(&mac.tokens.to_string())
+        }
+        expr => {
+            return Err(parse::Error::new(
+                expr.span(),
+                "format string must be a string literal or a `concat!` or `stringify!` call",
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn macro_name(path: &Path) -> Option<String> {
+    path.segments
+        .last()
+        .map(|segment| segment.ident.to_string())
+}
+
+/// Binds the named parameters (`{name}`) of a format string to their arguments
+///
+/// `names` are the names returned by `defmt_parser::parse_with_names`. Each one is bound to the
+/// `name = value` argument of the same name or, if there's none, to the variable `name` captured
+/// from the scope of the macro call. The values are appended to `args`, after the positional
+/// arguments; the `name = value` arguments that are not used are returned.
+fn bind_named_args(
+    names: &[&str],
+    args: &mut Vec<Expr>,
+    mut named: Vec<Field>,
+    span: Span2,
+) -> Vec<Field> {
+    for name in names {
+        let value = match named.iter().position(|(ident, _)| ident == name) {
+            Some(i) => named.remove(i).1,
+            None => ident_expr(name, span),
+        };
+        args.push(value);
+    }
+    named
+}
+
+/// Fails if a field is named like a named parameter of the format string, e.g. `peeer` for
+/// `{peer}`: that's more likely a misspelled named argument than a key-value field
+fn check_misspelled_fields(fields: &[Field], names: &[&str]) -> parse::Result<()> {
+    for (field, _) in fields {
+        let field_name = field.to_string();
+        if let Some(name) = names
+            .iter()
+            .find(|name| defmt_parser::is_one_edit_away(&field_name, name))
+        {
+            return Err(parse::Error::new(
+                field.span(),
+                format!(
+                    "key-value field `{}` looks like a misspelling of the named argument `{}`; \
+                     rename the field if it's not a typo",
+                    field, name
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the arguments of a format string that has no key-value fields, e.g. of `write!`
+///
+/// Named parameters are bound like in `bind_named_args`; all `name = value` arguments must be used.
+fn format_string_args(
+    names: &[&str],
+    rest: Option<(Token![,], Punctuated<Expr, Token![,]>)>,
+    span: Span2,
+) -> parse::Result<Vec<Expr>> {
+    let (mut args, named) = split_fields(rest.into_iter().flat_map(|(_, exprs)| exprs))?;
+    match bind_named_args(names, &mut args, named, span).first() {
+        Some((name, _)) => Err(parse::Error::new(
+            name.span(),
+            format!("named argument `{}` is not used by the format string", name),
+        )),
+        None => Ok(args),
+    }
+}
+
 impl Parse for FormatArgs {
     fn parse(input: ParseStream) -> parse::Result<Self> {
         Ok(Self {
-            litstr: input.parse()?,
+            litstr: parse_format_string(input)?,
             rest: if input.is_empty() {
                 None
             } else {
@@ -995,11 +1844,13 @@ pub fn internp(ts: TokenStream) -> TokenStream {
     if cfg!(feature = "unstable-test") {
         quote!({ defmt::export::fetch_add_string_index() as u16 })
     } else {
+        let build_id_entry = mk_build_id_entry(&format_ident!("S"), &sym, quote!(crate));
         quote!({
             #[cfg_attr(target_os = "macos", link_section = #section_macos)]
             #[cfg_attr(not(target_os = "macos"), link_section = #section)]
             #[export_name = #sym]
             static S: u8 = 0;
+            #build_id_entry
             &S as *const u8 as u16
         })
     }
@@ -1010,19 +1861,19 @@ pub fn internp(ts: TokenStream) -> TokenStream {
 pub fn write(ts: TokenStream) -> TokenStream {
     let write = parse_macro_input!(ts as Write);
     let ls = write.litstr.value();
-    let fragments = match defmt_parser::parse(&ls, ParserMode::Strict) {
-        Ok(args) => args,
+    let (fragments, arg_names) = match defmt_parser::parse_with_names(&ls, ParserMode::Strict) {
+        Ok(parsed) => parsed,
         Err(e) => {
-            return parse::Error::new(write.litstr.span(), e)
+            return format_string_error(&write.litstr, e)
                 .to_compile_error()
                 .into()
         }
     };
 
-    let args: Vec<_> = write
-        .rest
-        .map(|(_, exprs)| exprs.into_iter().collect())
-        .unwrap_or_default();
+    let args = match format_string_args(&arg_names, write.rest, write.litstr.span()) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     let (pats, exprs) = match Codegen::new(&fragments, args.len(), write.litstr.span()) {
         Ok(cg) => (cg.pats, cg.exprs),
@@ -1046,6 +1897,48 @@ pub fn write(ts: TokenStream) -> TokenStream {
     .into()
 }
 
+#[proc_macro]
+pub fn encode(ts: TokenStream) -> TokenStream {
+    // same syntax as `write!`, with the buffer in place of the formatter
+    let encode = parse_macro_input!(ts as Write);
+    let ls = encode.litstr.value();
+    let (fragments, arg_names) = match defmt_parser::parse_with_names(&ls, ParserMode::Strict) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            return format_string_error(&encode.litstr, e)
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let args = match format_string_args(&arg_names, encode.rest, encode.litstr.span()) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let (pats, exprs) = match Codegen::new(&fragments, args.len(), encode.litstr.span()) {
+        Ok(cg) => (cg.pats, cg.exprs),
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let buf = &encode.fmt;
+    let sym = mksym(&ls, Level::Info.as_str(), true);
+    quote!({
+        match (#buf, #(&(#args)),*) {
+            (_buf_, #(#pats),*) => {
+                let mut _writer_ = defmt::export::SliceWriter::new(_buf_);
+                // SAFETY `_fmt_` is dropped before `_writer_`
+                let mut _fmt_ = unsafe { defmt::InternalFormatter::to_slice(&mut _writer_) };
+                _fmt_.header(&defmt::export::istr(#sym));
+                #(#exprs;)*
+                _fmt_.finalize();
+                _writer_.finish()
+            }
+        }
+    })
+    .into()
+}
+
 /// work around restrictions on length and allowed characters imposed by macos linker
 /// returns (note the comma character for macos):
 ///   under macos: ".defmt," + 16 character hex digest of symbol's hash
@@ -1062,20 +1955,52 @@ fn mksection(macos: bool, prefix: &str, symbol: &str) -> String {
     format!(".defmt{}", sub_section)
 }
 
-fn mkstatic(varname: Ident2, string: &str, tag: &str) -> TokenStream2 {
-    let sym = symbol::Symbol::new(tag, string).mangle();
+fn mkstatic(varname: Ident2, string: &str, tag: &str, fields: &[String]) -> TokenStream2 {
+    let sym = symbol::Symbol::new(tag, string)
+        .with_fields(fields)
+        .mangle();
     let section = mksection(false, "", &sym);
     let section_macos = mksection(true, "", &sym);
 
+    let build_id_entry = mk_build_id_entry(&varname, &sym, quote!(defmt));
+
     quote!(
         #[cfg_attr(target_os = "macos", link_section = #section_macos)]
         #[cfg_attr(not(target_os = "macos"), link_section = #section)]
         #[export_name = #sym]
         static #varname: u8 = 0;
+        #build_id_entry
+    )
+}
+
+/// Registers the interned string `varname` for the build ID (`build-id` feature)
+///
+/// `krate` is the path to the `defmt` crate; `internp!` is only used within `defmt` itself
+fn mk_build_id_entry(varname: &Ident2, sym: &str, krate: TokenStream2) -> TokenStream2 {
+    if !cfg!(feature = "build-id") {
+        return quote!();
+    }
+
+    let entry = format_ident!("{}_BUILD_ID", varname);
+    quote!(
+        #[cfg_attr(not(target_os = "macos"), link_section = ".defmt_build_id")]
+        #[used]
+        static #entry: #krate::export::BuildIdEntry =
+            #krate::export::BuildIdEntry::new(&#varname, #sym);
     )
 }
 
 fn mksym(string: &str, tag: &str, is_log_statement: bool) -> TokenStream2 {
+    mksym_with_fields(string, tag, is_log_statement, &[])
+}
+
+/// Like `mksym` but also records the names of the key-value `fields` of a log statement
+fn mksym_with_fields(
+    string: &str,
+    tag: &str,
+    is_log_statement: bool,
+    fields: &[String],
+) -> TokenStream2 {
     // NOTE we rely on this variable name when extracting file location information from the DWARF
     // without it we have no other mean to differentiate static variables produced by `info!` vs
     // produced by `intern!` (or `internp`)
@@ -1087,7 +2012,7 @@ fn mksym(string: &str, tag: &str, is_log_statement: bool) -> TokenStream2 {
     if cfg!(feature = "unstable-test") {
         quote!({ defmt::export::fetch_add_string_index() })
     } else {
-        let statik = mkstatic(varname.clone(), string, tag);
+        let statik = mkstatic(varname.clone(), string, tag, fields);
         quote!({
             #statik
             &#varname as *const u8 as usize
@@ -1107,7 +2032,7 @@ impl Parse for Write {
         Ok(Self {
             fmt: input.parse()?,
             _comma: input.parse()?,
-            litstr: input.parse()?,
+            litstr: parse_format_string(input)?,
             rest: if input.is_empty() {
                 None
             } else {
@@ -1172,6 +2097,13 @@ impl Codegen {
 
                 defmt_parser::Type::Format => exprs.push(quote!(_fmt_.fmt(#arg, false))),
                 defmt_parser::Type::FormatSlice => exprs.push(quote!(_fmt_.fmt_slice(#arg))),
+                defmt_parser::Type::FormatSequence => {
+                    return Err(parse::Error::new(
+                        span,
+                        "`{=__internal_FormatSequence}` is reserved for the `Formatter` builders \
+                         (e.g. `debug_struct`)",
+                    ))
+                }
                 defmt_parser::Type::FormatArray(len) => exprs.push(quote!(_fmt_.fmt_array({
                     let tmp: &[_; #len] = #arg;
                     tmp
@@ -25,6 +25,9 @@ pub struct Symbol<'a> {
 
     /// Symbol data for use by the host tooling. Interpretation depends on `tag`.
     data: &'a str,
+
+    /// Names of the key-value fields of a log statement; omitted from the symbol when empty
+    fields: &'a [String],
 }
 
 impl<'a> Symbol<'a> {
@@ -43,15 +46,32 @@ impl<'a> Symbol<'a> {
             },
             tag: format!("defmt_{}", tag),
             data,
+            fields: &[],
         }
     }
 
+    pub fn with_fields(mut self, fields: &'a [String]) -> Self {
+        self.fields = fields;
+        self
+    }
+
     pub fn mangle(&self) -> String {
+        let fields = if self.fields.is_empty() {
+            String::new()
+        } else {
+            let names = self
+                .fields
+                .iter()
+                .map(|name| format!(r#""{}""#, escape(name)))
+                .collect::<Vec<_>>();
+            format!(r#","fields":[{}]"#, names.join(","))
+        };
         format!(
-            r#"{{"package":"{}","tag":"{}","data":"{}","disambiguator":"{}"}}"#,
+            r#"{{"package":"{}","tag":"{}","data":"{}"{},"disambiguator":"{}"}}"#,
             escape(&self.package),
             escape(&self.tag),
             escape(self.data),
+            fields,
             self.disambiguator,
         )
     }
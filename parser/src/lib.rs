@@ -12,14 +12,17 @@
 
 mod types;
 
-use std::{borrow::Cow, ops::Range, str::FromStr};
+use std::{borrow::Cow, fmt, ops::Range, str::FromStr};
 
 pub use crate::types::Type;
 
-/// A parameter of the form `{{0=Type:hint}}` in a format string.
+/// A parameter of the form `{{0=Type:hint}}` or `{{name=Type:hint}}` in a format string.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Parameter {
     /// The argument index to display at this position.
+    ///
+    /// Named parameters are assigned the indices after the positional ones (see
+    /// [`parse_with_names`]).
     pub index: usize,
     /// The type of the argument to display, e.g. '=u8', '=bool'.
     pub ty: Type,
@@ -27,6 +30,45 @@ pub struct Parameter {
     pub hint: Option<DisplayHint>,
 }
 
+/// An error in a format string
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    /// What's wrong
+    pub message: Cow<'static, str>,
+    /// The byte range of the format string that the error is about
+    pub span: Range<usize>,
+    /// The parameter that contains the error, fixed, e.g. `{=u8}` for `{:u8}`; set when the fix is
+    /// obvious
+    pub suggestion: Option<String>,
+}
+
+impl Error {
+    fn new(message: impl Into<Cow<'static, str>>, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            suggestion: None,
+        }
+    }
+
+    fn with_suggestion(mut self, suggestion: String) -> Self {
+        self.suggestion = Some(suggestion);
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "; did you mean `{}`?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
 /// All display hints
 #[derive(Clone, Debug, PartialEq)]
 pub enum DisplayHint {
@@ -112,7 +154,8 @@ pub enum Fragment<'f> {
 ///
 /// ```notrust
 /// param := '{' [ argument ] [ '=' argtype ] [ ':' format_spec ] '}'
-/// argument := integer
+/// argument := integer | identifier
+/// identifier := ( letter | '_' ) ( letter | digit | '_' )*
 ///
 /// argtype := bitfield | '?' | format-array | '[?]' | byte-array | '[u8]' | 'istr' | 'str' |
 ///     'bool' | 'char' | 'u8' | 'u16' | 'u32' | 'u64' | 'u128' | 'usize' | 'i8' | 'i16' | 'i32' |
@@ -232,13 +275,33 @@ pub enum ParserMode {
     ForwardsCompatible,
 }
 
+/// Splits the argument name off the contents of a parameter, e.g. `name` in `name=u8:x`
+///
+/// Returns `None` if the parameter has no name, e.g. if its argument is an index.
+fn split_name(input: &str) -> Option<(&str, &str)> {
+    let end = input
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(input.len());
+    let name = &input[..end];
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => Some((name, &input[end..])),
+        _ => None,
+    }
+}
+
 /// Parse `Param` from `&str`
 ///
 /// * example `input`: `0=Type:hint` (note: no curly braces)
-fn parse_param(mut input: &str, mode: ParserMode) -> Result<Param, Cow<'static, str>> {
+///
+/// The spans of the errors are relative to `input`; their suggestions replace all of `input`.
+fn parse_param(param: &str, mode: ParserMode) -> Result<Param, Error> {
     const TYPE_PREFIX: &str = "=";
     const HINT_PREFIX: &str = ":";
 
+    // Position of what's left of `input` within `param`.
+    let pos = |input: &str| param.len() - input.len();
+    let mut input = param;
+
     // First, optional argument index.
     let mut index = None;
     let index_end = input
@@ -249,7 +312,7 @@ fn parse_param(mut input: &str, mode: ParserMode) -> Result<Param, Cow<'static,
         index = Some(
             input[..index_end]
                 .parse::<usize>()
-                .map_err(|e| e.to_string())?,
+                .map_err(|e| Error::new(e.to_string(), 0..index_end))?,
         );
     }
 
@@ -257,13 +320,15 @@ fn parse_param(mut input: &str, mode: ParserMode) -> Result<Param, Cow<'static,
     let mut ty = Type::default(); // when no explicit type; use the default one
     input = &input[index_end..];
 
-    if input.starts_with(TYPE_PREFIX) {
+    let explicit_type = input.starts_with(TYPE_PREFIX);
+    if explicit_type {
         // skip the prefix
         input = &input[TYPE_PREFIX.len()..];
 
         // type is delimited by `HINT_PREFIX` or end-of-string
         let type_end = input.find(HINT_PREFIX).unwrap_or_else(|| input.len());
         let type_fragment = &input[..type_end];
+        let type_span = pos(input)..pos(input) + type_end;
 
         const FORMAT_ARRAY_START: &str = "[?;";
         const U8_ARRAY_START: &str = "[u8;";
@@ -272,25 +337,33 @@ fn parse_param(mut input: &str, mode: ParserMode) -> Result<Param, Cow<'static,
         ty = match type_fragment.parse() {
             Ok(ty) => ty,
             _ if input.starts_with(U8_ARRAY_START) => {
-                let len = parse_array(&type_fragment[U8_ARRAY_START.len()..])?;
+                let len = parse_array(&type_fragment[U8_ARRAY_START.len()..])
+                    .map_err(|e| Error::new(e, type_span))?;
                 Type::U8Array(len)
             }
             _ if input.starts_with(FORMAT_ARRAY_START) => {
-                let len = parse_array(&type_fragment[FORMAT_ARRAY_START.len()..])?;
+                let len = parse_array(&type_fragment[FORMAT_ARRAY_START.len()..])
+                    .map_err(|e| Error::new(e, type_span))?;
                 Type::FormatArray(len)
             }
             _ => match parse_range(type_fragment) {
                 // Check for bitfield syntax.
                 Some((_, used)) if used != type_fragment.len() => {
-                    return Err("trailing data after bitfield range".into());
+                    return Err(Error::new("trailing data after bitfield range", type_span));
                 }
                 Some((range, _)) => Type::BitField(range),
                 None => {
-                    return Err(format!(
-                        "malformed format string (invalid type specifier `{}`)",
-                        input
-                    )
-                    .into());
+                    let error = Error::new(
+                        format!(
+                            "malformed format string (invalid type specifier `{}`)",
+                            input
+                        ),
+                        type_span.clone(),
+                    );
+                    return Err(match similar_type(type_fragment) {
+                        Some(ty) => error.with_suggestion(replace(param, type_span, ty)),
+                        None => error,
+                    });
                 }
             },
         };
@@ -302,6 +375,7 @@ fn parse_param(mut input: &str, mode: ParserMode) -> Result<Param, Cow<'static,
     let mut hint = None;
 
     if input.starts_with(HINT_PREFIX) {
+        let hint_span = pos(input)..param.len();
         // skip the prefix
         input = &input[HINT_PREFIX.len()..];
 
@@ -309,48 +383,120 @@ fn parse_param(mut input: &str, mode: ParserMode) -> Result<Param, Cow<'static,
             Some(a) => a,
             None => match mode {
                 ParserMode::Strict => {
-                    return Err(format!("unknown display hint: {:?}", input).into());
+                    let error = Error::new(
+                        format!("unknown display hint: {:?}", input),
+                        hint_span.clone(),
+                    );
+                    return Err(if input == "us" {
+                        error.with_suggestion(replace(param, hint_span, ":µs"))
+                    } else if input.parse::<Type>().is_ok() && !explicit_type {
+                        // old `{:u8}` syntax
+                        let ty = format!("={}", input);
+                        error.with_suggestion(replace(param, hint_span, &ty))
+                    } else {
+                        error
+                    });
                 }
                 ParserMode::ForwardsCompatible => DisplayHint::Unknown(input.to_owned()),
             },
         });
     } else if !input.is_empty() {
-        return Err(format!("unexpected content {:?} in format string", input).into());
+        return Err(Error::new(
+            format!("unexpected content {:?} in format string", input),
+            pos(input)..param.len(),
+        ));
     }
 
     Ok(Param { index, ty, hint })
 }
 
+/// Returns the type whose name `ty` is a typo of, e.g. `u8` for `U8` or `u88`
+fn similar_type(ty: &str) -> Option<&'static str> {
+    const TYPES: &[&str] = &[
+        "u8", "u16", "u24", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128",
+        "isize", "f32", "f64", "bool", "str", "istr", "char", "[u8]", "?", "[?]",
+    ];
+
+    let ty = ty.to_lowercase();
+    TYPES
+        .iter()
+        .copied()
+        .find(|candidate| ty == *candidate)
+        .or_else(|| {
+            TYPES
+                .iter()
+                .copied()
+                .find(|candidate| candidate.len() > 1 && is_one_edit_away(&ty, candidate))
+        })
+}
+
+/// Returns `true` if `a` becomes `b` by inserting, removing or replacing a single character
+///
+/// Used to suggest corrections, e.g. of misspelled types.
+pub fn is_one_edit_away(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<_>, Vec<_>) = (a.chars().collect(), b.chars().collect());
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if long.len() - short.len() > 1 {
+        return false;
+    }
+    let prefix = short.iter().zip(&long).take_while(|(x, y)| x == y).count();
+    if short.len() == long.len() {
+        prefix < short.len() && short[prefix + 1..] == long[prefix + 1..]
+    } else {
+        short[prefix..] == long[prefix + 1..]
+    }
+}
+
+/// Returns `s` with the `range` replaced by `with`
+fn replace(s: &str, range: Range<usize>, with: &str) -> String {
+    format!("{}{}{}", &s[..range.start], with, &s[range.end..])
+}
+
+/// `offset` is the position of `unescaped_literal` in the format string
 fn push_literal<'f>(
     frag: &mut Vec<Fragment<'f>>,
     unescaped_literal: &'f str,
-) -> Result<(), Cow<'static, str>> {
+    offset: usize,
+) -> Result<(), Error> {
     // Replace `{{` with `{` and `}}` with `}`. Single braces are errors.
+    let unmatched = |brace: char, pos: usize| {
+        Error::new(
+            format!("unmatched `{}` in format string", brace),
+            offset + pos..offset + pos + 1,
+        )
+        .with_suggestion(format!("{0}{0}", brace))
+    };
 
     // Scan for single braces first. The rest is trivial.
-    let mut last_open = false;
-    let mut last_close = false;
-    for c in unescaped_literal.chars() {
+    let mut last_open = None;
+    let mut last_close = None;
+    for (pos, c) in unescaped_literal.char_indices() {
         match c {
-            '{' => last_open = !last_open,
-            '}' => last_close = !last_close,
+            '{' => last_open = if last_open.is_some() { None } else { Some(pos) },
+            '}' => {
+                last_close = if last_close.is_some() {
+                    None
+                } else {
+                    Some(pos)
+                }
+            }
             _ => {
-                if last_open {
-                    return Err("unmatched `{` in format string".into());
+                if let Some(pos) = last_open {
+                    return Err(unmatched('{', pos));
                 }
-                if last_close {
-                    return Err("unmatched `}` in format string".into());
+                if let Some(pos) = last_close {
+                    return Err(unmatched('}', pos));
                 }
             }
         }
     }
 
     // Handle trailing unescaped `{` or `}`.
-    if last_open {
-        return Err("unmatched `{` in format string".into());
+    if let Some(pos) = last_open {
+        return Err(unmatched('{', pos));
     }
-    if last_close {
-        return Err("unmatched `}` in format string".into());
+    if let Some(pos) = last_close {
+        return Err(unmatched('}', pos));
     }
 
     // FIXME: This always allocates a `String`, so the `Cow` is useless.
@@ -387,18 +533,37 @@ where
     }
 }
 
-pub fn parse<'f>(
+pub fn parse<'f>(format_string: &'f str, mode: ParserMode) -> Result<Vec<Fragment<'f>>, Error> {
+    parse_with_names(format_string, mode).map(|(fragments, _)| fragments)
+}
+
+/// Parses `format_string` like [`parse`] and also returns the names of its named parameters
+///
+/// The names are in the order of their first use in the format string. The first name is assigned
+/// the argument index after the last positional argument, the second name the one after that, and
+/// so on.
+pub fn parse_with_names<'f>(
     format_string: &'f str,
     mode: ParserMode,
-) -> Result<Vec<Fragment<'f>>, Cow<'static, str>> {
+) -> Result<(Vec<Fragment<'f>>, Vec<&'f str>), Error> {
     let mut fragments = Vec::new();
 
+    // Position of each parameter in the format string, braces included.
+    let mut param_spans = Vec::new();
+
+    // Names of the named parameters, and the fragments that use them.
+    let mut names = Vec::new();
+    let mut named_fragments = Vec::new();
+
     // Index after the `}` of the last format specifier.
     let mut end_pos = 0;
 
     // Next argument index assigned to a parameter without an explicit one.
     let mut next_arg_index = 0;
 
+    // Number of positional arguments, i.e. one more than the largest index.
+    let mut positional_count = 0;
+
     let mut chars = format_string.char_indices();
     while let Some((brace_pos, ch)) = chars.next() {
         if ch != '{' {
@@ -416,26 +581,54 @@ pub fn parse<'f>(
         if brace_pos > end_pos {
             // There's a literal fragment with at least 1 character before this parameter fragment.
             let unescaped_literal = &format_string[end_pos..brace_pos];
-            push_literal(&mut fragments, unescaped_literal)?;
+            push_literal(&mut fragments, unescaped_literal, end_pos)?;
         }
 
         // Else, this is a format specifier. It ends at the next `}`.
-        let len = chars
-            .as_str()
-            .find('}')
-            .ok_or("missing `}` in format string")?;
+        let len = chars.as_str().find('}').ok_or_else(|| {
+            Error::new(
+                "missing `}` in format string",
+                brace_pos..format_string.len(),
+            )
+        })?;
         end_pos = brace_pos + 1 + len + 1;
+        param_spans.push(brace_pos..end_pos);
 
         // Parse the contents inside the braces.
         let param_str = &format_string[brace_pos + 1..][..len];
-        let param = parse_param(param_str, mode)?;
-        fragments.push(Fragment::Parameter(Parameter {
-            index: param.index.unwrap_or_else(|| {
+        let (name, param_str) = match split_name(param_str) {
+            Some((name, rest)) => (Some(name), rest),
+            None => (None, param_str),
+        };
+        let name_str = name.unwrap_or("");
+        let param = parse_param(param_str, mode).map_err(|e| {
+            // make the error relative to the format string
+            let offset = brace_pos + 1 + name_str.len();
+            Error {
+                message: e.message,
+                span: offset + e.span.start..offset + e.span.end,
+                suggestion: e.suggestion.map(|s| format!("{{{}{}}}", name_str, s)),
+            }
+        })?;
+        let index = if let Some(name) = name {
+            // The index is offset by the number of positional arguments once that is known.
+            named_fragments.push(fragments.len());
+            names.iter().position(|n| *n == name).unwrap_or_else(|| {
+                names.push(name);
+                names.len() - 1
+            })
+        } else {
+            let index = param.index.unwrap_or_else(|| {
                 // If there is no explicit index, assign the next one.
                 let idx = next_arg_index;
                 next_arg_index += 1;
                 idx
-            }),
+            });
+            positional_count = positional_count.max(index + 1);
+            index
+        };
+        fragments.push(Fragment::Parameter(Parameter {
+            index,
             ty: param.ty,
             hint: param.hint,
         }));
@@ -443,45 +636,65 @@ pub fn parse<'f>(
 
     // Trailing literal.
     if end_pos != format_string.len() {
-        push_literal(&mut fragments, &format_string[end_pos..])?;
+        push_literal(&mut fragments, &format_string[end_pos..], end_pos)?;
+    }
+
+    // Named arguments come after the positional ones.
+    for i in named_fragments {
+        if let Fragment::Parameter(param) = &mut fragments[i] {
+            param.index += positional_count;
+        }
     }
+    let arg_name = |index: usize| match index.checked_sub(positional_count) {
+        Some(name_index) => format!("`{}`", names[name_index]),
+        None => index.to_string(),
+    };
 
     // Check for argument type conflicts.
     let mut args = Vec::new();
-    for frag in &fragments {
-        if let Fragment::Parameter(Parameter { index, ty, .. }) = frag {
-            if args.len() <= *index {
-                args.resize(*index + 1, None);
-            }
+    let params = fragments.iter().filter_map(|frag| match frag {
+        Fragment::Parameter(param) => Some(param),
+        Fragment::Literal(_) => None,
+    });
+    for (Parameter { index, ty, .. }, span) in params.zip(param_spans) {
+        if args.len() <= *index {
+            args.resize(*index + 1, None);
+        }
 
-            match &mut args[*index] {
-                none @ None => {
-                    *none = Some(ty.clone());
-                }
-                Some(other_ty) => match (other_ty, ty) {
-                    // FIXME: Bitfield range shouldn't be part of the type.
-                    (Type::BitField(_), Type::BitField(_)) => {}
-                    (a, b) if a != b => {
-                        return Err(format!(
-                            "conflicting types for argument {}: used as {:?} and {:?}",
-                            index, a, ty
-                        )
-                        .into());
-                    }
-                    _ => {}
-                },
+        match &mut args[*index] {
+            none @ None => {
+                *none = Some(ty.clone());
             }
+            Some(other_ty) => match (other_ty, ty) {
+                // FIXME: Bitfield range shouldn't be part of the type.
+                (Type::BitField(_), Type::BitField(_)) => {}
+                (a, b) if a != b => {
+                    return Err(Error::new(
+                        format!(
+                            "conflicting types for argument {}: used as {:?} and {:?}",
+                            arg_name(*index),
+                            a,
+                            ty
+                        ),
+                        span,
+                    ));
+                }
+                _ => {}
+            },
         }
     }
 
     // Check that argument indices are dense (all arguments must be used).
     for (index, arg) in args.iter().enumerate() {
         if arg.is_none() {
-            return Err(format!("argument {} is not used in this format string", index).into());
+            return Err(Error::new(
+                format!("argument {} is not used in this format string", index),
+                0..format_string.len(),
+            ));
         }
     }
 
-    Ok(fragments)
+    Ok((fragments, names))
 }
 
 #[cfg(test)]
@@ -843,6 +1056,15 @@ mod tests {
                 hint: None,
             })
         );
+
+        assert_eq!(
+            parse_param("=__internal_FormatSequence", ParserMode::Strict),
+            Ok(Param {
+                index: None,
+                ty: Type::FormatSequence,
+                hint: None,
+            })
+        );
     }
 
     #[test]
@@ -942,6 +1164,65 @@ mod tests {
         assert!(parse("{2=u8}{1=u16}", ParserMode::Strict).is_err());
     }
 
+    #[test]
+    fn named() {
+        assert_eq!(
+            parse_with_names("{a=u8} {=u16} {b} {a=u8:x}", ParserMode::Strict),
+            Ok((
+                vec![
+                    Fragment::Parameter(Parameter {
+                        index: 1,
+                        ty: Type::U8,
+                        hint: None,
+                    }),
+                    Fragment::Literal(" ".into()),
+                    Fragment::Parameter(Parameter {
+                        index: 0,
+                        ty: Type::U16,
+                        hint: None,
+                    }),
+                    Fragment::Literal(" ".into()),
+                    Fragment::Parameter(Parameter {
+                        index: 2,
+                        ty: Type::Format,
+                        hint: None,
+                    }),
+                    Fragment::Literal(" ".into()),
+                    Fragment::Parameter(Parameter {
+                        index: 1,
+                        ty: Type::U8,
+                        hint: Some(DisplayHint::Hexadecimal {
+                            alternate: false,
+                            uppercase: false,
+                            zero_pad: 0,
+                        }),
+                    }),
+                ],
+                vec!["a", "b"]
+            ))
+        );
+
+        // names may contain digits and underscores but not start with a digit
+        assert_eq!(
+            parse_with_names("{_x1=u8}", ParserMode::Strict).map(|(_, names)| names),
+            Ok(vec!["_x1"])
+        );
+        assert!(parse("{1x=u8}", ParserMode::Strict).is_err());
+
+        // the names come after explicitly indexed arguments too
+        assert_eq!(
+            parse("{x=u8}{1=u8}{0=u8}", ParserMode::Strict)
+                .unwrap()
+                .iter()
+                .map(|frag| match frag {
+                    Fragment::Parameter(param) => param.index,
+                    Fragment::Literal(_) => unreachable!(),
+                })
+                .collect::<Vec<_>>(),
+            [2, 1, 0]
+        );
+    }
+
     #[test]
     fn range() {
         assert_eq!(
@@ -1034,31 +1315,66 @@ mod tests {
     #[test]
     fn error_msg() {
         assert_eq!(
-            parse("{=dunno}", ParserMode::Strict),
+            parse("{=dunno}", ParserMode::Strict).map_err(|e| e.message),
             Err("malformed format string (invalid type specifier `dunno`)".into())
         );
 
         assert_eq!(
-            parse("{dunno}", ParserMode::Strict),
-            Err("unexpected content \"dunno\" in format string".into())
+            parse("{dunno-x}", ParserMode::Strict).map_err(|e| e.message),
+            Err("unexpected content \"-x\" in format string".into())
         );
 
         assert_eq!(
-            parse("{=u8;x}", ParserMode::Strict),
+            parse("{=u8;x}", ParserMode::Strict).map_err(|e| e.message),
             Err("malformed format string (invalid type specifier `u8;x`)".into())
         );
 
         assert_eq!(
-            parse("{dunno=u8:x}", ParserMode::Strict),
-            Err("unexpected content \"dunno=u8:x\" in format string".into())
+            parse("{dunno=u8:x} {dunno=u16}", ParserMode::Strict).map_err(|e| e.message),
+            Err("conflicting types for argument `dunno`: used as U8 and U16".into())
         );
 
         assert_eq!(
-            parse("{0dunno}", ParserMode::Strict),
+            parse("{0dunno}", ParserMode::Strict).map_err(|e| e.message),
             Err("unexpected content \"dunno\" in format string".into())
         );
     }
 
+    #[test]
+    fn error_spans() {
+        let error = |format_string| parse(format_string, ParserMode::Strict).unwrap_err();
+        let span = |format_string| error(format_string).span;
+        let suggestion = |format_string| error(format_string).suggestion;
+
+        assert_eq!(span("x={=u8:dunno}"), 6..12);
+        assert_eq!(suggestion("x={=u8:dunno}"), None);
+
+        assert_eq!(span("{=U8}"), 2..4);
+        assert_eq!(suggestion("{=U8}"), Some("{=u8}".to_owned()));
+        assert_eq!(suggestion("{0=u88:x}"), Some("{0=u8:x}".to_owned()));
+        assert_eq!(suggestion("{=bol}"), Some("{=bool}".to_owned()));
+
+        // old syntax
+        assert_eq!(span("{:u8}"), 1..4);
+        assert_eq!(suggestion("{:u8}"), Some("{=u8}".to_owned()));
+        assert_eq!(suggestion("{len:u8}"), Some("{len=u8}".to_owned()));
+        assert_eq!(suggestion("{=?:u8}"), None);
+
+        assert_eq!(suggestion("{=u32:us}"), Some("{=u32:µs}".to_owned()));
+
+        assert_eq!(span("a } b"), 2..3);
+        assert_eq!(suggestion("a } b"), Some("}}".to_owned()));
+
+        assert_eq!(span("{=u8} {0=u16}"), 6..13);
+        assert_eq!(span("x={=u8"), 2..6);
+        assert_eq!(span("{1=u8}"), 0..6);
+
+        assert_eq!(
+            error("{=U8}").to_string(),
+            "malformed format string (invalid type specifier `U8`); did you mean `{=u8}`?"
+        );
+    }
+
     #[test]
     fn brace_escape() {
         // Stray braces.
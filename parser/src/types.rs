@@ -18,6 +18,9 @@ pub enum Type {
     FormatArray(usize), // FIXME: This `usize` is not the target's `usize`; use `u64` instead?
     /// `{=[?]}`
     FormatSlice,
+    /// A sequence of `Format` values of any length, each preceded by a marker byte; written by
+    /// the `Formatter` builders (e.g. `debug_list`)
+    FormatSequence,
 
     I8,
     I16,
@@ -72,6 +75,7 @@ impl FromStr for Type {
             "[u8]" => Type::U8Slice,
             "?" => Type::Format,
             "[?]" => Type::FormatSlice,
+            "__internal_FormatSequence" => Type::FormatSequence,
             "char" => Type::Char,
             _ => return Err(()),
         })
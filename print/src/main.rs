@@ -1,20 +1,40 @@
 use std::{
-    env, fs,
-    io::{self, Read},
-    path::PathBuf,
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use anyhow::anyhow;
-use defmt_decoder::Table;
+use defmt_decoder::{DecodeError, Frame, Locations, StreamDecoder, Table};
+use serde::Serialize;
 use structopt::StructOpt;
 
 /// Prints defmt-encoded logs to stdout
 #[derive(StructOpt)]
 #[structopt(name = "defmt-print")]
 struct Opts {
-    #[structopt(short, parse(from_os_str), required_unless_one(&["version"]))]
+    #[structopt(short, parse(from_os_str), required_unless_one(&["version", "dictionary"]))]
     elf: Option<PathBuf>,
 
+    /// Decode with a dictionary file (see `--export-dictionary`) instead of an ELF file
+    #[structopt(long, parse(from_os_str), conflicts_with("elf"))]
+    dictionary: Option<PathBuf>,
+
+    /// Write a dictionary file, which can decode the logs without the ELF file, and exit
+    #[structopt(long, parse(from_os_str), requires("elf"))]
+    export_dictionary: Option<PathBuf>,
+
+    /// Output format: `text`, or `json` for one JSON object per line
+    #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+    format: OutputFormat,
+
+    /// The data may start in the middle of a log frame, e.g. when it has been read out of a ring
+    /// buffer; a partial first frame is skipped silently
+    #[structopt(long)]
+    mid_stream: bool,
+
     #[structopt(short = "V", long)]
     version: bool,
     // may want to add this later
@@ -23,6 +43,24 @@ struct Opts {
     // TODO add file path argument; always use stdin for now
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow!("unknown output format `{}`", s)),
+        }
+    }
+}
+
 const READ_BUFFER_SIZE: usize = 1024;
 
 fn main() -> anyhow::Result<()> {
@@ -34,24 +72,42 @@ fn main() -> anyhow::Result<()> {
 
     let verbose = false;
     defmt_decoder::log::init_logger(verbose, |metadata| {
-        // We display *all* defmt frames, but nothing else.
-        defmt_decoder::log::is_defmt_frame(metadata)
+        // We display *all* defmt frames, and our own warnings and errors.
+        defmt_decoder::log::is_defmt_frame(metadata) || metadata.level() <= log::Level::Warn
     });
 
-    let bytes = fs::read(&opts.elf.unwrap())?;
-
-    let table = Table::parse(&bytes)?.ok_or_else(|| anyhow!(".defmt data not found"))?;
-    let locs = table.get_locations(&bytes)?;
+    let (table, locs) = if let Some(elf) = &opts.elf {
+        let bytes = fs::read(elf)?;
 
-    let locs = if table.indices().all(|idx| locs.contains_key(&(idx as u64))) {
-        Some(locs)
+        let table = Table::parse(&bytes)?.ok_or_else(|| anyhow!(".defmt data not found"))?;
+        let locs = table.get_locations(&bytes)?;
+        (table, Some(locs))
     } else {
-        log::warn!("(BUG) location info is incomplete; it will be omitted from the output");
-        None
+        // NOTE(unwrap) `structopt` requires either an ELF file or a dictionary
+        let dictionary = fs::read(opts.dictionary.as_ref().unwrap())?;
+        Table::parse_dictionary(&dictionary)?
+    };
+
+    let locs = match locs {
+        Some(locs) if table.indices().all(|idx| locs.contains_key(&(idx as u64))) => Some(locs),
+        Some(_) => {
+            log::warn!("(BUG) location info is incomplete; it will be omitted from the output");
+            None
+        }
+        None => None,
     };
 
+    if let Some(path) = &opts.export_dictionary {
+        let file = BufWriter::new(File::create(path)?);
+        return table.write_dictionary(locs.as_ref(), file);
+    }
+
     let mut buf = [0; READ_BUFFER_SIZE];
-    let mut frames = vec![];
+    let mut stream = if opts.mid_stream {
+        StreamDecoder::new_mid_stream(&table)
+    } else {
+        StreamDecoder::new(&table)
+    };
 
     let current_dir = env::current_dir()?;
     let stdin = io::stdin();
@@ -59,50 +115,92 @@ fn main() -> anyhow::Result<()> {
     loop {
         let n = stdin.read(&mut buf)?;
 
-        frames.extend_from_slice(&buf[..n]);
+        stream.received(&buf[..n]);
 
         loop {
-            match table.decode(&frames) {
-                Ok((frame, consumed)) => {
-                    // NOTE(`[]` indexing) all indices in `table` have already been
-                    // verified to exist in the `locs` map
-                    let loc = locs.as_ref().map(|locs| &locs[&frame.index()]);
-
-                    let (mut file, mut line, mut mod_path) = (None, None, None);
-                    if let Some(loc) = loc {
-                        let relpath = if let Ok(relpath) = loc.file.strip_prefix(&current_dir) {
-                            relpath
-                        } else {
-                            // not relative; use full path
-                            &loc.file
-                        };
-                        file = Some(relpath.display().to_string());
-                        line = Some(loc.line as u32);
-                        mod_path = Some(loc.module.clone());
+            match stream.decode() {
+                Ok(frame) => {
+                    if frame.lost_frames() != 0 {
+                        log::warn!("{} frames lost", frame.lost_frames());
+                    }
+                    match opts.format {
+                        OutputFormat::Text => {
+                            forward_to_logger(&frame, locs.as_ref(), &current_dir)
+                        }
+                        OutputFormat::Json => print_json(&frame, locs.as_ref(), &current_dir)?,
                     }
-
-                    // Forward the defmt frame to our logger.
-                    defmt_decoder::log::log_defmt(
-                        &frame,
-                        file.as_deref(),
-                        line,
-                        mod_path.as_deref(),
-                    );
-
-                    let num_frames = frames.len();
-                    frames.rotate_left(consumed);
-                    frames.truncate(num_frames - consumed);
                 }
-                Err(defmt_decoder::DecodeError::UnexpectedEof) => break,
-                Err(defmt_decoder::DecodeError::Malformed) => {
-                    log::error!("failed to decode defmt data: {:x?}", frames);
-                    return Err(defmt_decoder::DecodeError::Malformed.into());
+                Err(DecodeError::UnexpectedEof) => break,
+                // e.g. we attached mid-stream or the target dropped data
+                Err(DecodeError::Malformed(e)) => {
+                    log::warn!("skipping malformed defmt data: {}", e)
                 }
+                // every other frame would be decoded with the wrong table
+                Err(e @ DecodeError::BuildIdMismatch { .. }) => return Err(e.into()),
             }
         }
     }
 }
 
+fn forward_to_logger(frame: &Frame, locs: Option<&Locations>, current_dir: &Path) {
+    let (file, line, mod_path) = location(frame, locs, current_dir);
+
+    // Forward the defmt frame to our logger.
+    defmt_decoder::log::log_defmt(frame, file.as_deref(), line, mod_path.as_deref());
+}
+
+/// A frame and the location of its log statement, as printed by `--format json`
+#[derive(Serialize)]
+struct JsonFrame<'a> {
+    #[serde(flatten)]
+    frame: &'a Frame<'a>,
+    file: Option<String>,
+    line: Option<u32>,
+    module: Option<String>,
+}
+
+fn print_json(frame: &Frame, locs: Option<&Locations>, current_dir: &Path) -> anyhow::Result<()> {
+    let (file, line, module) = location(frame, locs, current_dir);
+    let json = JsonFrame {
+        frame,
+        file,
+        line,
+        module,
+    };
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer(&mut stdout, &json)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Returns the file, line and module path of the log statement that produced `frame`
+fn location(
+    frame: &Frame,
+    locs: Option<&Locations>,
+    current_dir: &Path,
+) -> (Option<String>, Option<u32>, Option<String>) {
+    // NOTE(`[]` indexing) all indices in `table` have already been
+    // verified to exist in the `locs` map
+    let loc = match locs {
+        Some(locs) => &locs[&frame.index()],
+        None => return (None, None, None),
+    };
+
+    let relpath = if let Ok(relpath) = loc.file.strip_prefix(current_dir) {
+        relpath
+    } else {
+        // not relative; use full path
+        &loc.file
+    };
+    (
+        Some(relpath.display().to_string()),
+        Some(loc.line as u32),
+        Some(loc.module.clone()),
+    )
+}
+
 /// Report version from Cargo.toml _(e.g. "0.1.4")_ and supported `defmt`-versions.
 ///
 /// Used by `--version` flag.
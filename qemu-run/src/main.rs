@@ -10,7 +10,7 @@ use std::{
 };
 
 use anyhow::{anyhow, bail};
-use defmt_decoder::{DecodeError, Table};
+use defmt_decoder::{DecodeError, StreamDecoder, Table};
 use process::Child;
 
 fn main() -> Result<(), anyhow::Error> {
@@ -64,27 +64,29 @@ fn notmain() -> Result<Option<i32>, anyhow::Error> {
         .take()
         .ok_or_else(|| anyhow!("failed to acquire child's stdout handle"))?;
 
-    let mut frames = vec![];
+    let mut stream = StreamDecoder::new(&table);
     let mut readbuf = [0; 256];
     let exit_code;
     loop {
         let n = stdout.read(&mut readbuf)?;
 
         if n != 0 {
-            frames.extend_from_slice(&readbuf[..n]);
+            stream.received(&readbuf[..n]);
 
-            decode(&mut frames, &table)?;
+            decode(&mut stream)?;
         }
 
         if let Some(status) = child.0.try_wait()? {
             exit_code = status.code();
 
-            stdout.read_to_end(&mut frames)?;
-            decode(&mut frames, &table)?;
-            if !frames.is_empty() {
+            let mut rest = vec![];
+            stdout.read_to_end(&mut rest)?;
+            stream.received(&rest);
+            decode(&mut stream)?;
+            if !stream.remaining().is_empty() {
                 return Err(anyhow!(
                     "couldn't decode all data (remaining: {:x?})",
-                    frames
+                    stream.remaining()
                 ));
             }
 
@@ -95,20 +97,19 @@ fn notmain() -> Result<Option<i32>, anyhow::Error> {
     Ok(exit_code)
 }
 
-fn decode(frames: &mut Vec<u8>, table: &Table) -> Result<(), DecodeError> {
+fn decode(stream: &mut StreamDecoder) -> Result<(), anyhow::Error> {
     loop {
-        match table.decode(&frames) {
-            Ok((frame, consumed)) => {
-                println!("{}", frame.display(true));
-                let n = frames.len();
-                frames.rotate_left(consumed);
-                frames.truncate(n - consumed);
+        match stream.decode() {
+            Ok(frame) => {
+                if frame.lost_frames() != 0 {
+                    eprintln!("{} frames lost", frame.lost_frames());
+                }
+                println!("{}", frame.display(true))
             }
             Err(DecodeError::UnexpectedEof) => return Ok(()),
-            Err(DecodeError::Malformed) => {
-                eprintln!("failed to decode defmt data: {:x?}", frames);
-                return Err(DecodeError::Malformed);
-            }
+            Err(DecodeError::Malformed(e)) => eprintln!("skipping malformed defmt data: {}", e),
+            // every other frame would be decoded with the wrong table
+            Err(e @ DecodeError::BuildIdMismatch { .. }) => return Err(e.into()),
         }
     }
 }
@@ -0,0 +1,58 @@
+//! Read-modify-write operations on the state shared by all log frames: the sequence number, the
+//! span ID and whether the build ID has been sent
+
+#[cfg(feature = "build-id")]
+use core::sync::atomic::AtomicBool;
+#[cfg(feature = "sequence-numbers")]
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Replaces the value of `$atomic` with `$f(value)` and returns the previous value
+///
+/// `$width` is the size of the atomic in bits, as used by `cfg(target_has_atomic)`.
+macro_rules! update {
+    ($atomic:expr, $width:literal, $f:expr) => {{
+        #[cfg(target_has_atomic = $width)]
+        let previous = $atomic
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                Some($f(value))
+            })
+            .unwrap();
+
+        // NOTE the target has no read-modify-write operations (e.g. ARMv6-M); this relies on the
+        // logger being acquired, so nothing else is logging concurrently. With a
+        // `preemptible-logger` only the build ID flag gets here (`span!` is rejected and sequence
+        // numbers can't be enabled); a race merely sends the build ID twice
+        #[cfg(not(target_has_atomic = $width))]
+        let previous = {
+            let value = $atomic.load(Ordering::Relaxed);
+            $atomic.store($f(value), Ordering::Relaxed);
+            value
+        };
+
+        previous
+    }};
+}
+
+/// Increments `counter`, wrapping around on overflow, and returns its previous value
+///
+/// Must be called with the logger acquired.
+#[cfg(feature = "sequence-numbers")]
+pub(crate) fn next_u8(counter: &AtomicU8) -> u8 {
+    update!(counter, "8", |n: u8| n.wrapping_add(1))
+}
+
+/// Increments `counter`, wrapping around on overflow, and returns its previous value
+///
+/// Must be called with the logger acquired.
+pub(crate) fn next_u16(counter: &AtomicU16) -> u16 {
+    update!(counter, "16", |n: u16| n.wrapping_add(1))
+}
+
+/// Sets `flag` and returns its previous value
+///
+/// Must be called with the logger acquired.
+#[cfg(feature = "build-id")]
+pub(crate) fn set(flag: &AtomicBool) -> bool {
+    update!(flag, "8", |_| true)
+}
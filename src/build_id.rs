@@ -0,0 +1,117 @@
+//! Build ID (`build-id` feature)
+//!
+//! The build ID is a hash of the interned strings in the `.defmt` section: their indices and their
+//! symbol names. The firmware sends it, as a log frame, ahead of its first log frame and the host
+//! compares it against the ID it computes from the ELF file (or dictionary) it decodes with.
+//!
+//! Global loggers that drop their oldest frames (e.g. a flight recorder) would lose the build ID
+//! frame; they send it again with `export::send_build_id` before handing their frames to the host.
+//!
+//! The firmware can't read the `.defmt` section (it's not loaded) so every interned string gets an
+//! entry in the `.defmt_build_id` section instead; the build ID is computed from those entries.
+
+use core::sync::atomic::AtomicBool;
+
+use crate::{atomic, InternalFormatter, Str};
+
+/// Implementation detail
+///
+/// Ties the `string` static of an interned string to the hash of its symbol name
+#[doc(hidden)]
+#[repr(C)]
+pub struct BuildIdEntry {
+    string: &'static u8,
+    hash: u32,
+}
+
+impl BuildIdEntry {
+    /// Implementation detail
+    pub const fn new(string: &'static u8, symbol: &str) -> Self {
+        Self {
+            string,
+            hash: hash(symbol.as_bytes()),
+        }
+    }
+
+    fn contribution(&self) -> u32 {
+        // the address of a string in the `.defmt` section is its index
+        let index = self.string as *const u8 as usize as u16;
+        let mut hash = FNV_OFFSET_BASIS;
+        for byte in index.to_le_bytes().iter().chain(&self.hash.to_le_bytes()) {
+            hash = (hash ^ *byte as u32).wrapping_mul(FNV_PRIME);
+        }
+        hash
+    }
+}
+
+/// Returns the build ID of the firmware
+///
+/// This is the ID that `defmt-decoder` computes from the ELF file.
+pub fn build_id() -> u32 {
+    extern "C" {
+        static __defmt_build_id_start: BuildIdEntry;
+        static __defmt_build_id_end: BuildIdEntry;
+    }
+
+    unsafe {
+        let mut entry = &__defmt_build_id_start as *const BuildIdEntry;
+        let end = &__defmt_build_id_end as *const BuildIdEntry;
+        // the sum doesn't depend on the order in which the linker placed the entries
+        let mut id = 0u32;
+        while entry < end {
+            id = id.wrapping_add((*entry).contribution());
+            entry = entry.add(1);
+        }
+        id
+    }
+}
+
+// NOTE the host tooling looks for this tag; `.defmt.build_id` is placed at the start of the
+// `.defmt` section by the linker script so this string always has index 0
+#[cfg_attr(target_os = "macos", link_section = ".defmt,build_id")]
+#[cfg_attr(not(target_os = "macos"), link_section = ".defmt.build_id")]
+#[export_name = r#"{"package":"defmt","tag":"defmt_build_id","data":"defmt build ID: {=u32:#010x}","disambiguator":"0"}"#]
+// NOTE `get_locations` in `defmt-decoder` relies on this variable name
+static DEFMT_LOG_STATEMENT: u8 = 0;
+
+#[cfg_attr(not(target_os = "macos"), link_section = ".defmt_build_id")]
+#[used]
+static DEFMT_LOG_STATEMENT_BUILD_ID: BuildIdEntry = BuildIdEntry::new(
+    &DEFMT_LOG_STATEMENT,
+    // must match the `export_name` above
+    r#"{"package":"defmt","tag":"defmt_build_id","data":"defmt build ID: {=u32:#010x}","disambiguator":"0"}"#,
+);
+
+/// Sends the build ID frame if it hasn't been sent yet
+///
+/// Must be called with the logger acquired and before the first log frame is started.
+pub(crate) fn send_once(fmt: &mut InternalFormatter) {
+    static SENT: AtomicBool = AtomicBool::new(false);
+
+    if !atomic::set(&SENT) {
+        send(fmt);
+    }
+}
+
+/// Sends the build ID frame
+pub(crate) fn send(fmt: &mut InternalFormatter) {
+    fmt.header(&Str {
+        address: &DEFMT_LOG_STATEMENT as *const u8 as usize as u16,
+    });
+    fmt.u32(&build_id());
+    fmt.finalize();
+}
+
+// 32-bit FNV-1a
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+const fn hash(bytes: &[u8]) -> u32 {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u32).wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash
+}
@@ -0,0 +1,236 @@
+//! Builders for `Format` implementations that write a value in several steps (see
+//! `Formatter::debug_struct`)
+
+#[cfg(feature = "unstable-test")]
+use crate as defmt;
+use crate::{Format, Formatter, Str};
+
+/// Precedes each element of a `{=__internal_FormatSequence}`
+const ELEMENT: u8 = 1;
+/// Terminates a `{=__internal_FormatSequence}`
+const END: u8 = 0;
+
+impl<'a> Formatter<'a> {
+    /// Creates a builder that formats a struct with named fields, like `Header { src: 1, dst: 2 }`
+    ///
+    /// Unlike `write!`, the builder can be called any number of times, e.g. to leave out fields
+    /// that are not set. The names of the struct and of its fields are interned strings (see
+    /// [`intern!`]); the values are formatted with their `Format` implementation.
+    ///
+    /// [`intern!`]: macro.intern.html
+    ///
+    /// ```
+    /// struct Header {
+    ///     src: u8,
+    ///     dst: Option<u8>,
+    /// }
+    ///
+    /// impl defmt::Format for Header {
+    ///     fn format(&self, f: defmt::Formatter) {
+    ///         let mut header = f.debug_struct(defmt::intern!("Header"));
+    ///         header.field(defmt::intern!("src"), &self.src);
+    ///         if let Some(dst) = &self.dst {
+    ///             header.field(defmt::intern!("dst"), dst);
+    ///         }
+    ///         header.finish();
+    ///     }
+    /// }
+    /// ```
+    pub fn debug_struct(self, name: Str) -> DebugStruct<'a> {
+        let seq = Sequence::new(
+            self,
+            defmt_macros::internp!("{=istr} {{ {=__internal_FormatSequence} }}"),
+        );
+        seq.fmt.inner.istr(&name);
+        DebugStruct { seq }
+    }
+
+    /// Creates a builder that formats a tuple struct, like `Handle(1, 2)`
+    ///
+    /// See [`Formatter::debug_struct`].
+    pub fn debug_tuple(self, name: Str) -> DebugTuple<'a> {
+        let seq = Sequence::new(
+            self,
+            defmt_macros::internp!("{=istr}({=__internal_FormatSequence})"),
+        );
+        seq.fmt.inner.istr(&name);
+        DebugTuple { seq }
+    }
+
+    /// Creates a builder that formats a list of values, like `[1, 2, 3]`
+    ///
+    /// Unlike `{=[?]}`, the values don't need to be in a slice, nor of the same type.
+    ///
+    /// ```
+    /// struct Node {
+    ///     value: u8,
+    ///     next: Option<&'static Node>,
+    /// }
+    ///
+    /// struct List(Option<&'static Node>);
+    ///
+    /// impl defmt::Format for List {
+    ///     fn format(&self, f: defmt::Formatter) {
+    ///         let mut list = f.debug_list();
+    ///         let mut node = self.0;
+    ///         while let Some(n) = node {
+    ///             list.entry(&n.value);
+    ///             node = n.next;
+    ///         }
+    ///         list.finish();
+    ///     }
+    /// }
+    /// ```
+    pub fn debug_list(self) -> DebugList<'a> {
+        DebugList {
+            seq: Sequence::new(
+                self,
+                defmt_macros::internp!("[{=__internal_FormatSequence}]"),
+            ),
+        }
+    }
+
+    /// Creates a builder that formats a map, like `{1: 2, 3: 4}`
+    ///
+    /// See [`Formatter::debug_list`].
+    pub fn debug_map(self) -> DebugMap<'a> {
+        DebugMap {
+            seq: Sequence::new(
+                self,
+                defmt_macros::internp!("{{{=__internal_FormatSequence}}}"),
+            ),
+        }
+    }
+}
+
+/// Writes the elements of a `{=__internal_FormatSequence}`; terminates it when dropped
+struct Sequence<'a> {
+    fmt: Formatter<'a>,
+}
+
+impl<'a> Sequence<'a> {
+    /// `format` is the format string of the value; it contains the sequence
+    fn new(fmt: Formatter<'a>, format: u16) -> Self {
+        if fmt.inner.needs_tag() {
+            fmt.inner.tag(&format);
+        }
+        Self { fmt }
+    }
+
+    fn element(&mut self, f: impl FnOnce(Formatter)) {
+        self.fmt.inner.u8(&ELEMENT);
+        // the elements can have any type so they are always tagged, even in a `{=[?]}` slice
+        self.fmt.inner.with_tag(f);
+    }
+}
+
+impl Drop for Sequence<'_> {
+    fn drop(&mut self) {
+        self.fmt.inner.u8(&END);
+    }
+}
+
+/// A builder that formats a struct with named fields; created with [`Formatter::debug_struct`]
+///
+/// The value is complete when the builder is finished or dropped.
+pub struct DebugStruct<'a> {
+    seq: Sequence<'a>,
+}
+
+impl DebugStruct<'_> {
+    /// Adds a field with the interned `name`
+    pub fn field(&mut self, name: Str, value: &impl Format) -> &mut Self {
+        self.seq.element(|f| {
+            f.inner.tag(&defmt_macros::internp!("{=istr}: {=?}"));
+            f.inner.istr(&name);
+            f.inner.fmt(value, false);
+        });
+        self
+    }
+
+    /// Finishes the struct; this is the same as dropping the builder
+    pub fn finish(self) {}
+}
+
+/// A builder that formats a tuple struct; created with [`Formatter::debug_tuple`]
+///
+/// The value is complete when the builder is finished or dropped.
+pub struct DebugTuple<'a> {
+    seq: Sequence<'a>,
+}
+
+impl DebugTuple<'_> {
+    /// Adds a field
+    pub fn field(&mut self, value: &impl Format) -> &mut Self {
+        self.seq.element(|f| f.inner.fmt(value, false));
+        self
+    }
+
+    /// Finishes the tuple struct; this is the same as dropping the builder
+    pub fn finish(self) {}
+}
+
+/// A builder that formats a list; created with [`Formatter::debug_list`]
+///
+/// The value is complete when the builder is finished or dropped.
+pub struct DebugList<'a> {
+    seq: Sequence<'a>,
+}
+
+impl DebugList<'_> {
+    /// Adds an entry
+    pub fn entry(&mut self, value: &impl Format) -> &mut Self {
+        self.seq.element(|f| f.inner.fmt(value, false));
+        self
+    }
+
+    /// Adds the entries of an iterator
+    pub fn entries<I>(&mut self, entries: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: Format,
+    {
+        for entry in entries {
+            self.entry(&entry);
+        }
+        self
+    }
+
+    /// Finishes the list; this is the same as dropping the builder
+    pub fn finish(self) {}
+}
+
+/// A builder that formats a map; created with [`Formatter::debug_map`]
+///
+/// The value is complete when the builder is finished or dropped.
+pub struct DebugMap<'a> {
+    seq: Sequence<'a>,
+}
+
+impl DebugMap<'_> {
+    /// Adds an entry
+    pub fn entry(&mut self, key: &impl Format, value: &impl Format) -> &mut Self {
+        self.seq.element(|f| {
+            f.inner.tag(&defmt_macros::internp!("{=?}: {=?}"));
+            f.inner.fmt(key, false);
+            f.inner.fmt(value, false);
+        });
+        self
+    }
+
+    /// Adds the `(key, value)` entries of an iterator
+    pub fn entries<K, V, I>(&mut self, entries: I) -> &mut Self
+    where
+        K: Format,
+        V: Format,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        for (key, value) in entries {
+            self.entry(&key, &value);
+        }
+        self
+    }
+
+    /// Finishes the map; this is the same as dropping the builder
+    pub fn finish(self) {}
+}
@@ -0,0 +1,57 @@
+//! Encoding log frames into a buffer instead of sending them to the global logger; see
+//! [`encode!`](crate::encode)
+
+/// Error returned by [`encode!`](crate::encode)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EncodeError {
+    /// The log frame doesn't fit in the buffer
+    Overflow,
+}
+
+/// Implementation detail
+///
+/// Writes the log frame encoded by `encode!` into a buffer
+#[doc(hidden)]
+pub struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    overflow: bool,
+}
+
+impl<'a> SliceWriter<'a> {
+    /// Implementation detail
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            overflow: false,
+        }
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        if self.overflow {
+            return;
+        }
+
+        match self.buf.get_mut(self.pos..self.pos + bytes.len()) {
+            Some(buf) => {
+                buf.copy_from_slice(bytes);
+                self.pos += bytes.len();
+            }
+            // keep the rest of the frame out too; a partial frame can't be decoded
+            None => self.overflow = true,
+        }
+    }
+
+    /// Implementation detail
+    ///
+    /// Returns the size of the log frame
+    pub fn finish(self) -> Result<usize, EncodeError> {
+        if self.overflow {
+            Err(EncodeError::Overflow)
+        } else {
+            Ok(self.pos)
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Per-frame integrity check (`crc` feature)
+//!
+//! CRC-16/CCITT-FALSE (polynomial `0x1021`, initial value `0xffff`) of the frame's bytes, before
+//! they are encoded. It's computed bit by bit to keep the code size down; log frames are short.
+
+pub(crate) struct Crc {
+    crc: u16,
+}
+
+impl Crc {
+    pub(crate) const fn new() -> Self {
+        Self { crc: 0xffff }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.crc ^= (*byte as u16) << 8;
+            for _ in 0..8 {
+                self.crc = if self.crc & 0x8000 != 0 {
+                    (self.crc << 1) ^ 0x1021
+                } else {
+                    self.crc << 1
+                };
+            }
+        }
+    }
+
+    /// Returns the CRC of the bytes seen so far and starts over
+    pub(crate) fn finish(&mut self) -> u16 {
+        core::mem::replace(self, Self::new()).crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        let mut crc = Crc::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0x29b1);
+        assert_eq!(crc.finish(), 0xffff);
+    }
+}
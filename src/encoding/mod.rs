@@ -0,0 +1,25 @@
+//! Framing of the log stream
+//!
+//! Log frames are encoded as they are written to the global logger. The encoder state lives in the
+//! `InternalFormatter` of each log statement so frames being produced from different execution
+//! contexts (when the logger supports that) never share encoder state.
+//!
+//! The encoding is selected at compile time with the `encoding-*` Cargo features and recorded in
+//! the `.defmt` section (`_defmt_encoding_` symbol) so the host knows how to decode the stream.
+//!
+//! With the `crc` feature every frame ends with a CRC of its (unencoded) bytes.
+
+#[cfg(any(test, feature = "crc"))]
+mod crc;
+
+#[cfg(not(feature = "encoding-rzcobs"))]
+mod raw;
+#[cfg(any(test, feature = "encoding-rzcobs"))]
+mod rzcobs;
+
+#[cfg(feature = "crc")]
+pub(crate) use crc::Crc;
+#[cfg(not(feature = "encoding-rzcobs"))]
+pub(crate) use raw::Encoder;
+#[cfg(feature = "encoding-rzcobs")]
+pub(crate) use rzcobs::Encoder;
@@ -0,0 +1,14 @@
+/// Writes frames back-to-back, without any framing
+pub(crate) struct Encoder;
+
+impl Encoder {
+    pub(crate) const fn new() -> Self {
+        Self
+    }
+
+    pub(crate) fn write(&mut self, data: &[u8], mut write: impl FnMut(&[u8])) {
+        write(data)
+    }
+
+    pub(crate) fn end_frame(&mut self, _write: impl FnMut(&[u8])) {}
+}
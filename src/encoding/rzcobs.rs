@@ -0,0 +1,149 @@
+//! Reverse-Zerocompressing-COBS (rzCOBS)
+//!
+//! Every frame is encoded such that it contains no `0x00` bytes and is then terminated with a
+//! `0x00` byte. A receiver that starts listening mid-stream (or loses bytes) can resynchronise at
+//! the next `0x00` byte.
+//!
+//! The encoding is designed to be decoded back-to-front, which lets the encoder stream bytes out
+//! without buffering the frame. Runs of non-zero bytes are written out as they are; each run is
+//! followed by a marker byte:
+//!
+//! - `0b0xxx_xxxx`: the previous 7 bytes (the first written byte is bit 0) contain zeros, which were
+//!   left out of the output, at the positions of the set bits. A short run at the end of the frame
+//!   is padded with set bits.
+//! - `0b1xxx_xxxx` (but not `0xff`): the previous `xxx_xxxx + 7` bytes are non-zero and followed by
+//!   a single zero.
+//! - `0xff`: the previous 134 bytes are non-zero and *not* followed by a zero.
+
+/// Streaming rzCOBS encoder
+pub(crate) struct Encoder {
+    /// Number of input bytes in the current run
+    run: u8,
+    /// Positions of the zeros in the current run, while the run is shorter than 7 bytes
+    zeros: u8,
+}
+
+impl Encoder {
+    pub(crate) const fn new() -> Self {
+        Self { run: 0, zeros: 0 }
+    }
+
+    pub(crate) fn write(&mut self, data: &[u8], mut write: impl FnMut(&[u8])) {
+        // non-zero bytes of `data` are written out in batches; this is where the current batch
+        // starts
+        let mut pending = 0;
+        for (i, &byte) in data.iter().enumerate() {
+            let marker = if self.run < 7 {
+                if byte == 0 {
+                    flush(&data[pending..i], &mut write);
+                    pending = i + 1;
+                    self.zeros |= 1 << self.run;
+                }
+                self.run += 1;
+
+                if self.run == 7 && self.zeros != 0 {
+                    Some(self.zeros)
+                } else {
+                    None
+                }
+            } else if byte == 0 {
+                flush(&data[pending..i], &mut write);
+                pending = i + 1;
+                Some((self.run - 7) | 0x80)
+            } else {
+                self.run += 1;
+
+                if self.run == 134 {
+                    Some(0xff)
+                } else {
+                    None
+                }
+            };
+
+            if let Some(marker) = marker {
+                flush(&data[pending..=i], &mut write);
+                pending = i + 1;
+                write(&[marker]);
+                self.run = 0;
+                self.zeros = 0;
+            }
+        }
+
+        flush(&data[pending..], &mut write);
+    }
+
+    /// Terminates the current frame
+    pub(crate) fn end_frame(&mut self, mut write: impl FnMut(&[u8])) {
+        match self.run {
+            0 => {}
+            1..=6 => write(&[(self.zeros | (0xff << self.run)) & 0x7f]),
+            _ => write(&[(self.run - 7) | 0x80]),
+        }
+        // frame delimiter
+        write(&[0]);
+
+        self.run = 0;
+        self.zeros = 0;
+    }
+}
+
+fn flush(bytes: &[u8], write: &mut impl FnMut(&[u8])) {
+    if !bytes.is_empty() {
+        write(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Encoder;
+
+    fn encode(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut encoder = Encoder::new();
+        for chunk in chunks {
+            encoder.write(chunk, |bytes| out.extend_from_slice(bytes));
+        }
+        encoder.end_frame(|bytes| out.extend_from_slice(bytes));
+        out
+    }
+
+    #[test]
+    fn short_frames() {
+        assert_eq!(encode(&[]), [0x00]);
+        assert_eq!(encode(&[&[0x00]]), [0x7f, 0x00]);
+        assert_eq!(encode(&[&[0x11]]), [0x11, 0x7e, 0x00]);
+        assert_eq!(encode(&[&[0x11, 0x00, 0x22]]), [0x11, 0x22, 0x7a, 0x00]);
+    }
+
+    #[test]
+    fn zeros_in_first_seven_bytes() {
+        assert_eq!(
+            encode(&[&[1, 0, 2, 3, 4, 5, 6, 7]]),
+            [1, 2, 3, 4, 5, 6, 0b10, 7, 0x7e, 0x00]
+        );
+    }
+
+    #[test]
+    fn long_runs() {
+        let data = [0xaa; 10];
+        assert_eq!(encode(&[&data, &[0]]), [&data[..], &[0x83, 0x00]].concat());
+
+        let data = [0xaa; 134];
+        assert_eq!(encode(&[&data]), [&data[..], &[0xff, 0x00]].concat());
+    }
+
+    #[test]
+    fn output_contains_no_zeros_but_the_delimiter() {
+        let data = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        let encoded = encode(&[&data]);
+        assert_eq!(encoded.last(), Some(&0));
+        assert!(!encoded[..encoded.len() - 1].contains(&0));
+    }
+
+    #[test]
+    fn chunking_does_not_change_output() {
+        let data = [1, 0, 0, 2, 3, 0, 4, 5, 6, 7, 8, 9, 0, 10, 0, 0];
+        let chunks = data.chunks(3).collect::<Vec<_>>();
+        assert_eq!(encode(&chunks), encode(&[&data]));
+    }
+}
@@ -1,5 +1,11 @@
 use crate::Str;
 
+#[cfg(all(feature = "build-id", not(feature = "unstable-test")))]
+pub use crate::build_id::BuildIdEntry;
+pub use crate::encode::SliceWriter;
+#[cfg(feature = "runtime-filter")]
+pub use crate::filter::{enabled, ModulePath};
+
 #[cfg(feature = "unstable-test")]
 thread_local! {
     static I: core::sync::atomic::AtomicU16 =
@@ -53,6 +59,28 @@ pub fn write(bytes: &[u8]) {
     unsafe { _defmt_write(bytes) }
 }
 
+#[cfg(feature = "unstable-test")]
+pub fn flush() {}
+
+#[cfg(not(feature = "unstable-test"))]
+#[inline(never)]
+pub fn flush() {
+    extern "Rust" {
+        fn _defmt_flush();
+    }
+    unsafe { _defmt_flush() }
+}
+
+/// Sends the build ID frame (`build-id` feature); does nothing if the feature is disabled
+///
+/// For global loggers that drop their oldest frames, and with them the build ID frame that
+/// precedes the first log frame. Must be called with the logger acquired.
+#[cfg(not(feature = "unstable-test"))]
+pub fn send_build_id() {
+    #[cfg(feature = "build-id")]
+    crate::build_id::send(&mut crate::InternalFormatter::new());
+}
+
 /// For testing purposes
 #[cfg(feature = "unstable-test")]
 pub fn timestamp(_fmt: crate::Formatter<'_>) {}
@@ -65,6 +93,26 @@ pub fn timestamp(fmt: crate::Formatter<'_>) {
     unsafe { _defmt_timestamp(fmt) }
 }
 
+/// Returns `true` if the module at `path` is the module at `prefix` or one of its submodules
+///
+/// Used to apply the `DEFMT_LOG` filter at compile time.
+pub const fn path_matches(path: &str, prefix: &str) -> bool {
+    let (path, prefix) = (path.as_bytes(), prefix.as_bytes());
+    if path.len() < prefix.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < prefix.len() {
+        if path[i] != prefix[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    path.len() == prefix.len() || (path[i] == b':' && path.len() > i + 1 && path[i + 1] == b':')
+}
+
 /// Returns the interned string at `address`.
 pub fn istr(address: usize) -> Str {
     Str {
@@ -224,6 +272,14 @@ pub fn into_result<T: sealed::IntoResult>(x: T) -> Result<T::Ok, T::Error> {
     x.into_result()
 }
 
+/// Calls `f`; used by `#[instrument(ret)]`
+///
+/// The `FnOnce` bound lets the body of `f` return borrows of the variables it captured, e.g.
+/// `&mut self.field`.
+pub fn call_once<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
 /// For testing purposes
 #[cfg(feature = "unstable-test")]
 pub fn panic() -> ! {
@@ -235,5 +291,7 @@ pub fn panic() -> ! {
     extern "Rust" {
         fn _defmt_panic() -> !;
     }
+    // the panic handler may never return control to the logger (e.g. reset the device)
+    flush();
     unsafe { _defmt_panic() }
 }
@@ -0,0 +1,255 @@
+//! Runtime log filtering
+//!
+//! With the `runtime-filter` feature enabled, the log statements that are compiled in (see the
+//! `defmt-*` Cargo features) are also checked against the filter in this module before their
+//! arguments are evaluated. The filter starts out letting everything through.
+//!
+//! ``` ignore
+//! use defmt::filter::{self, LevelFilter};
+//!
+//! // only log warnings and errors ..
+//! filter::set_max_level(LevelFilter::Warn);
+//! // .. except for the `radio` module (and its submodules) of the application
+//! filter::set_module_level("app::radio", LevelFilter::Trace).unwrap();
+//! ```
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+pub use crate::LevelFilter;
+
+/// Error returned by [`set_module_level`] when all the module overrides are in use
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TooManyOverrides;
+
+/// Maximum number of per-module overrides
+pub const MAX_OVERRIDES: usize = 8;
+
+/// Only this many `::`-separated prefixes of a module path are considered by overrides
+const MAX_DEPTH: usize = 8;
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(LevelFilter::Trace as u8);
+
+/// Fast path: don't look at the overrides if none are set
+static HAS_OVERRIDES: AtomicBool = AtomicBool::new(false);
+
+struct Override {
+    /// Hash of the module path; `0` = unused
+    module: AtomicU32,
+    level: AtomicU8,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNUSED: Override = Override {
+    module: AtomicU32::new(0),
+    level: AtomicU8::new(0),
+};
+
+static OVERRIDES: [Override; MAX_OVERRIDES] = [UNUSED; MAX_OVERRIDES];
+
+/// Sets the most verbose level that is logged, by modules without an override
+pub fn set_max_level(level: LevelFilter) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed)
+}
+
+/// Returns the most verbose level that is logged, by modules without an override
+pub fn max_level() -> LevelFilter {
+    LevelFilter::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Overrides the maximum level for the module at `path` (e.g. `"app::radio"`) and its submodules
+///
+/// When several overrides apply to a module the one for the longest module path wins.
+///
+/// NOTE this function (and [`clear_module_levels`]) must not be called concurrently from different
+/// execution contexts (e.g. `main` and an interrupt handler); checking the filter from log
+/// statements is fine though.
+pub fn set_module_level(path: &str, level: LevelFilter) -> Result<(), TooManyOverrides> {
+    let module = hash(path.as_bytes());
+
+    let slot = OVERRIDES
+        .iter()
+        .find(|slot| slot.module.load(Ordering::Relaxed) == module)
+        .or_else(|| {
+            OVERRIDES
+                .iter()
+                .find(|slot| slot.module.load(Ordering::Relaxed) == 0)
+        })
+        .ok_or(TooManyOverrides)?;
+
+    // publish the level before the slot becomes visible
+    slot.level.store(level as u8, Ordering::Relaxed);
+    slot.module.store(module, Ordering::Release);
+    HAS_OVERRIDES.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Removes all the per-module overrides
+pub fn clear_module_levels() {
+    HAS_OVERRIDES.store(false, Ordering::Relaxed);
+    for slot in &OVERRIDES {
+        slot.module.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Implementation detail
+///
+/// Hashes of the `::`-separated prefixes of a module path, computed at compile time so module
+/// paths don't need to be stored on the target
+#[doc(hidden)]
+pub struct ModulePath {
+    /// Shortest prefix first; unused entries are `0`. The last used entry is the full path.
+    prefixes: [u32; MAX_DEPTH],
+}
+
+impl ModulePath {
+    /// Implementation detail
+    pub const fn new(path: &str) -> Self {
+        let bytes = path.as_bytes();
+        let mut prefixes = [0; MAX_DEPTH];
+        let mut depth = 0;
+        let mut hash = FNV_OFFSET_BASIS;
+
+        let mut i = 0;
+        while i < bytes.len() {
+            let is_separator = bytes[i] == b':' && i + 1 < bytes.len() && bytes[i + 1] == b':';
+            // keep the last entry for the full path
+            if is_separator && depth < MAX_DEPTH - 1 {
+                prefixes[depth] = non_zero(hash);
+                depth += 1;
+            }
+            hash = (hash ^ bytes[i] as u32).wrapping_mul(FNV_PRIME);
+            i += 1;
+        }
+        prefixes[depth] = non_zero(hash);
+
+        Self { prefixes }
+    }
+}
+
+/// Implementation detail
+///
+/// `level`: 1 = ERROR, .., 5 = TRACE (see `LevelFilter`)
+#[doc(hidden)]
+#[inline]
+pub fn enabled(level: u8, module: &ModulePath) -> bool {
+    let max_level = if HAS_OVERRIDES.load(Ordering::Acquire) {
+        module_level(module).unwrap_or_else(|| MAX_LEVEL.load(Ordering::Relaxed))
+    } else {
+        MAX_LEVEL.load(Ordering::Relaxed)
+    };
+    level <= max_level
+}
+
+#[inline(never)]
+fn module_level(module: &ModulePath) -> Option<u8> {
+    // longest prefix first
+    module
+        .prefixes
+        .iter()
+        .rev()
+        .filter(|prefix| **prefix != 0)
+        .find_map(|prefix| {
+            OVERRIDES.iter().find_map(|slot| {
+                if slot.module.load(Ordering::Acquire) == *prefix {
+                    Some(slot.level.load(Ordering::Relaxed))
+                } else {
+                    None
+                }
+            })
+        })
+}
+
+// 32-bit FNV-1a
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+fn hash(bytes: &[u8]) -> u32 {
+    let hash = bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(FNV_PRIME)
+    });
+    non_zero(hash)
+}
+
+/// `0` marks unused entries
+const fn non_zero(hash: u32) -> u32 {
+    if hash == 0 {
+        1
+    } else {
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_path_prefixes() {
+        let path = ModulePath::new("app::radio::spi");
+        assert_eq!(
+            path.prefixes[..4],
+            [
+                hash(b"app"),
+                hash(b"app::radio"),
+                hash(b"app::radio::spi"),
+                0
+            ]
+        );
+
+        let path = ModulePath::new("app");
+        assert_eq!(path.prefixes[..2], [hash(b"app"), 0]);
+
+        let path = ModulePath::new("a::b::c::d::e::f::g::h::i::j");
+        assert_eq!(path.prefixes[MAX_DEPTH - 2], hash(b"a::b::c::d::e::f::g"));
+        assert_eq!(
+            path.prefixes[MAX_DEPTH - 1],
+            hash(b"a::b::c::d::e::f::g::h::i::j")
+        );
+    }
+
+    // NOTE the filter is global state so everything is tested in a single test
+    #[test]
+    fn filter() {
+        const APP: ModulePath = ModulePath::new("app");
+        const RADIO: ModulePath = ModulePath::new("app::radio");
+        const SPI: ModulePath = ModulePath::new("app::radio::spi");
+        const TRACE: u8 = LevelFilter::Trace as u8;
+        const WARN: u8 = LevelFilter::Warn as u8;
+        const ERROR: u8 = LevelFilter::Error as u8;
+
+        assert!(enabled(TRACE, &APP));
+
+        set_max_level(LevelFilter::Warn);
+        assert_eq!(max_level(), LevelFilter::Warn);
+        assert!(!enabled(TRACE, &APP));
+        assert!(enabled(WARN, &APP));
+        assert!(enabled(ERROR, &APP));
+
+        set_module_level("app::radio", LevelFilter::Trace).unwrap();
+        assert!(!enabled(TRACE, &APP));
+        assert!(enabled(TRACE, &RADIO));
+        assert!(enabled(TRACE, &SPI));
+
+        set_module_level("app::radio::spi", LevelFilter::Off).unwrap();
+        assert!(enabled(TRACE, &RADIO));
+        assert!(!enabled(ERROR, &SPI));
+
+        // updates the existing override
+        set_module_level("app::radio", LevelFilter::Error).unwrap();
+        assert!(!enabled(WARN, &RADIO));
+
+        for i in 2..MAX_OVERRIDES {
+            set_module_level(&i.to_string(), LevelFilter::Off).unwrap();
+        }
+        assert_eq!(
+            set_module_level("app", LevelFilter::Off),
+            Err(TooManyOverrides)
+        );
+
+        clear_module_levels();
+        assert!(enabled(WARN, &SPI));
+        assert!(!enabled(TRACE, &RADIO));
+
+        set_max_level(LevelFilter::Trace);
+    }
+}
@@ -1,6 +1,13 @@
-use core::fmt::{self, Write as _};
+use core::{
+    fmt::{self, Write as _},
+    ptr::NonNull,
+};
 
-use crate::{export, Format};
+#[cfg(all(feature = "crc", not(feature = "unstable-test")))]
+use crate::encoding::Crc;
+#[cfg(not(feature = "unstable-test"))]
+use crate::encoding::Encoder;
+use crate::{export, export::SliceWriter, Format};
 
 /// Handle to a defmt logger.
 pub struct Formatter<'a> {
@@ -13,6 +20,15 @@ pub struct Formatter<'a> {
 pub struct InternalFormatter {
     #[cfg(feature = "unstable-test")]
     bytes: Vec<u8>,
+    #[cfg(not(feature = "unstable-test"))]
+    encoder: Encoder,
+    #[cfg(all(feature = "crc", not(feature = "unstable-test")))]
+    crc: Crc,
+    /// Where `encode!` puts the log frame; `None` for the global logger
+    slice: Option<NonNull<SliceWriter<'static>>>,
+    /// Level of the log frame, for the sinks; see `LevelFilter`
+    #[cfg(all(feature = "sinks", not(feature = "unstable-test")))]
+    level: u8,
     /// Whether to omit the tag of a `Format` value
     ///
     /// * this is disabled while formatting a `{:[?]}` value (second element on-wards)
@@ -24,7 +40,46 @@ pub struct InternalFormatter {
 impl InternalFormatter {
     #[cfg(not(feature = "unstable-test"))]
     pub fn write(&mut self, bytes: &[u8]) {
-        export::write(bytes)
+        #[cfg(feature = "crc")]
+        self.crc.update(bytes);
+        let output = self.output();
+        self.encoder.write(bytes, output)
+    }
+
+    /// Implementation detail
+    ///
+    /// Terminates the log frame; must be called once all of its data has been written
+    #[cfg(not(feature = "unstable-test"))]
+    pub fn finalize(&mut self) {
+        #[cfg(feature = "crc")]
+        {
+            let crc = self.crc.finish();
+            let output = self.output();
+            self.encoder.write(&crc.to_le_bytes(), output);
+        }
+        let output = self.output();
+        self.encoder.end_frame(output);
+        #[cfg(feature = "sinks")]
+        if self.slice.is_none() {
+            crate::sinks::end_frame(self.level);
+        }
+    }
+
+    /// Returns the function that receives the encoded bytes
+    #[cfg(not(feature = "unstable-test"))]
+    fn output(&self) -> impl FnMut(&[u8]) {
+        let slice = self.slice;
+        #[cfg(feature = "sinks")]
+        let level = self.level;
+        move |bytes| match slice {
+            None => {
+                export::write(bytes);
+                #[cfg(feature = "sinks")]
+                crate::sinks::write(level, bytes);
+            }
+            // SAFETY `to_slice` callers guarantee that the writer outlives the formatter
+            Some(mut writer) => unsafe { writer.as_mut().write(bytes) },
+        }
     }
 
     /// Implementation detail
@@ -35,7 +90,54 @@ impl InternalFormatter {
     #[cfg(not(feature = "unstable-test"))]
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        Self { omit_tag: false }
+        #[allow(unused_mut)]
+        let mut fmt = Self {
+            encoder: Encoder::new(),
+            #[cfg(feature = "crc")]
+            crc: Crc::new(),
+            slice: None,
+            // frames without a level (e.g. the build ID) go to all the sinks that are not off
+            #[cfg(feature = "sinks")]
+            level: crate::LevelFilter::Error as u8,
+            omit_tag: false,
+        };
+        #[cfg(feature = "build-id")]
+        crate::build_id::send_once(&mut fmt);
+        fmt
+    }
+
+    /// Implementation detail
+    ///
+    /// Creates a formatter that puts the log frame into `writer` (see `encode!`)
+    ///
+    /// # Safety
+    ///
+    /// `writer` must outlive the returned formatter
+    #[cfg(not(feature = "unstable-test"))]
+    pub unsafe fn to_slice(writer: &mut SliceWriter<'_>) -> Self {
+        Self {
+            encoder: Encoder::new(),
+            #[cfg(feature = "crc")]
+            crc: Crc::new(),
+            slice: Some(NonNull::from(writer).cast()),
+            #[cfg(feature = "sinks")]
+            level: crate::LevelFilter::Error as u8,
+            omit_tag: false,
+        }
+    }
+
+    /// Implementation detail
+    ///
+    /// Sets the level of the log frame (see `LevelFilter`); called before `header`
+    #[cfg(feature = "sinks")]
+    pub fn level(&mut self, level: u8) {
+        #[cfg(not(feature = "unstable-test"))]
+        {
+            self.level = level;
+        }
+        // frames are not sent anywhere in test mode
+        #[cfg(feature = "unstable-test")]
+        let _ = level;
     }
 
     // TODO turn these public methods in `export` free functions
@@ -218,10 +320,29 @@ impl InternalFormatter {
     #[inline(never)]
     pub fn header(&mut self, s: &Str) {
         self.istr(s);
+        // frames put into a buffer by `encode!` are not part of the log stream
+        #[cfg(feature = "sequence-numbers")]
+        self.u8(&if self.slice.is_none() {
+            next_sequence_number()
+        } else {
+            0
+        });
         export::timestamp(Formatter { inner: self });
     }
 }
 
+/// Returns the sequence number of the next log frame; it wraps around after 255
+///
+/// Must be called with the logger acquired.
+#[cfg(feature = "sequence-numbers")]
+fn next_sequence_number() -> u8 {
+    use core::sync::atomic::AtomicU8;
+
+    static NEXT: AtomicU8 = AtomicU8::new(0);
+
+    crate::atomic::next_u8(&NEXT)
+}
+
 /// An interned string created via [`intern!`].
 ///
 /// [`intern!`]: macro.intern.html
@@ -249,6 +370,18 @@ impl super::InternalFormatter {
     pub fn new() -> Self {
         Self {
             bytes: vec![],
+            slice: None,
+            omit_tag: false,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `writer` must outlive the returned formatter
+    pub unsafe fn to_slice(writer: &mut SliceWriter<'_>) -> Self {
+        Self {
+            bytes: vec![],
+            slice: Some(NonNull::from(writer).cast()),
             omit_tag: false,
         }
     }
@@ -260,4 +393,12 @@ impl super::InternalFormatter {
     pub fn write(&mut self, bytes: &[u8]) {
         self.bytes.extend_from_slice(bytes)
     }
+
+    // frames are not encoded in test mode
+    pub fn finalize(&mut self) {
+        if let Some(mut writer) = self.slice {
+            // SAFETY `to_slice` callers guarantee that the writer outlives the formatter
+            unsafe { writer.as_mut().write(&self.bytes) }
+        }
+    }
 }
@@ -0,0 +1,33 @@
+//! Log level thresholds, shared by the runtime filter and the sinks
+
+/// The most verbose level that is let through a filter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LevelFilter {
+    /// Nothing is logged
+    Off = 0,
+    /// ERROR
+    Error = 1,
+    /// WARN and up
+    Warn = 2,
+    /// INFO and up
+    Info = 3,
+    /// DEBUG and up
+    Debug = 4,
+    /// TRACE and up
+    Trace = 5,
+}
+
+impl LevelFilter {
+    #[cfg(any(feature = "runtime-filter", all(test, feature = "unstable-test")))]
+    pub(crate) fn from_u8(level: u8) -> Self {
+        match level {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+}
@@ -19,25 +19,64 @@
 extern crate alloc;
 
 mod adapter;
+mod atomic;
+#[cfg(all(feature = "build-id", not(feature = "unstable-test")))]
+mod build_id;
+mod builders;
+mod encode;
+// the test-mode formatter doesn't encode frames
+#[cfg_attr(feature = "unstable-test", allow(dead_code, unused_imports))]
+mod encoding;
 #[doc(hidden)]
 pub mod export;
+#[cfg(any(feature = "runtime-filter", all(test, feature = "unstable-test")))]
+pub mod filter;
 mod formatter;
 mod impls;
+mod level;
+#[cfg(all(feature = "sinks", not(feature = "unstable-test")))]
+pub mod sinks;
+mod span;
 #[cfg(all(test, feature = "unstable-test"))]
 mod tests;
 mod traits;
 
 pub use crate::{
     adapter::{Debug2Format, Display2Format},
+    builders::{DebugList, DebugMap, DebugStruct, DebugTuple},
+    encode::EncodeError,
     formatter::{Formatter, InternalFormatter, Str},
+    level::LevelFilter,
+    span::Span,
     traits::{Format, Logger},
 };
 
+#[cfg(all(feature = "build-id", not(feature = "unstable-test")))]
+pub use crate::build_id::build_id;
+#[cfg(all(feature = "sinks", not(feature = "unstable-test")))]
+pub use crate::sinks::Sink;
+
 #[cfg(all(test, not(feature = "unstable-test")))]
 compile_error!(
     "to run unit tests enable the `unstable-test` feature, e.g. `cargo t --features unstable-test`"
 );
 
+// sequence numbers are assigned when a frame is encoded but a preempted frame is committed after
+// the frames of the context that preempted it; the CRC and rzCOBS encoding rule out renumbering
+// the frames when they are committed
+#[cfg(all(feature = "preemptible-logger", feature = "sequence-numbers"))]
+compile_error!(
+    "the `sequence-numbers` feature can't be used with a global logger that can be preempted \
+     mid-frame (`preemptible-logger` feature): the frames would be numbered out of order"
+);
+
+// sinks are written to while a frame is being encoded
+#[cfg(all(feature = "preemptible-logger", feature = "sinks"))]
+compile_error!(
+    "the `sinks` feature can't be used with a global logger that can be preempted mid-frame \
+     (`preemptible-logger` feature): the sinks would receive interleaved frames"
+);
+
 /// Just like the [`core::assert!`] macro but `defmt` is used to log the panic message
 ///
 /// [`core::assert!`]: https://doc.rust-lang.org/core/macro.assert.html
@@ -227,6 +266,70 @@ pub use defmt_macros::warn;
 /// [`std::dbg!`]: https://doc.rust-lang.org/std/macro.dbg.html
 pub use defmt_macros::dbg;
 
+/// Enters a span: logs an enter frame now and an exit frame when the returned [`Span`] guard is
+/// dropped
+///
+/// Both frames carry the interned span name and an ID so the host can pair them, reconstruct the
+/// nesting of spans and compute how long a span took from the timestamps of the frames. Spans are
+/// logged, and filtered, at `TRACE` level.
+///
+/// ```
+/// # fn process() {}
+/// fn handle_irq() {
+///     let _span = defmt::span!("handle_irq");
+///     process();
+///     // the span is exited here
+/// }
+/// ```
+pub use defmt_macros::span;
+
+/// Logs the calls of a function: its entry, with the selected arguments, and its exit
+///
+/// The attribute takes the following, optional, arguments:
+///
+/// - `level = ".."`: the level of the log frames; `"trace"` by default. The frames are filtered
+///   like the log statements of that level.
+/// - `args(..)`: the arguments to log on entry; they must implement [`Format`]. No arguments are
+///   logged by default.
+/// - `ret`: also log the return value on exit; it must implement [`Format`].
+///
+/// The exit is logged on every return path, including early `return`s and `?`. `async` and
+/// `const` functions, and functions that return `impl Trait`, are not supported.
+///
+/// ```
+/// #[defmt::instrument(level = "debug", args(addr), ret)]
+/// fn read_register(addr: u8) -> u16 {
+///     // -> DEBUG read_register(addr=3)
+///     u16::from(addr) << 8
+///     // -> DEBUG read_register -> 768
+/// }
+/// # read_register(3);
+/// ```
+pub use defmt_macros::instrument;
+
+/// Encodes a log frame into a buffer instead of sending it to the global logger.
+///
+/// The syntax is the same as the one of the logging macros, except that the first argument is the
+/// buffer (`&mut [u8]`). Returns the size of the frame, or [`EncodeError::Overflow`] if it doesn't
+/// fit in the buffer (in which case the contents of the buffer are unspecified).
+///
+/// The frame is encoded like the frames sent to the global logger (including the timestamp and
+/// the encoding selected with the `encoding-*` features) and has the *info* level; the host
+/// decodes it with the ELF file of the firmware like any other frame. It is not subject to log
+/// level filtering and doesn't use the global logger.
+///
+/// # Example
+///
+/// ```
+/// let mut buf = [0; 32];
+/// let temperature = 21u8;
+/// if let Ok(len) = defmt::encode!(&mut buf, "temperature: {=u8}°C", temperature) {
+///     // send `buf[..len]` in a packet
+/// #   let _ = len;
+/// }
+/// ```
+pub use defmt_macros::encode;
+
 /// Writes formatted data to a [`Formatter`].
 ///
 /// [`Formatter`]: struct.Formatter.html
@@ -264,6 +367,22 @@ pub use defmt_macros::write;
 /// [`Logger`]: trait.Logger.html
 pub use defmt_macros::global_logger;
 
+/// Registers [`Sink`]s that receive the log frames next to the global logger (`sinks` feature).
+///
+/// Takes a list of `Type => max_level` pairs, where `Type` implements [`Sink`] and `max_level` is
+/// an expression of type [`LevelFilter`]. `sinks!` must only be used once across the crate graph.
+///
+/// # Example
+///
+/// ``` ignore
+/// defmt::sinks! {
+///     FlashRing => defmt::LevelFilter::Info,
+///     Uart => defmt::LevelFilter::Warn,
+/// }
+/// ```
+#[cfg(feature = "sinks")]
+pub use defmt_macros::sinks;
+
 /// Defines the global timestamp provider for defmt.
 ///
 /// This macro can be used to attach a timestamp or other data to every defmt message. Its syntax
@@ -287,6 +406,19 @@ pub use defmt_macros::timestamp;
 #[doc(hidden)] // documented as the `Format` trait instead
 pub use defmt_macros::Format;
 
+/// Sends the log frames buffered by the global logger, if any, to the host
+///
+/// This does nothing unless the global logger holds on to log frames (e.g. a flight recorder that
+/// only sends the most recent frames when something goes wrong); see [`Logger::flush`].
+/// `defmt::panic!` and the other panicking macros flush the global logger before calling the panic
+/// handler.
+///
+/// This function must not be called while a log frame is being written (e.g. from a `Format`
+/// implementation).
+pub fn flush() {
+    export::flush()
+}
+
 #[export_name = "__defmt_default_timestamp"]
 fn default_timestamp(_f: Formatter<'_>) {
     // By default, no timestamp is used.
@@ -0,0 +1,78 @@
+//! Additional destinations for the log frames (`sinks` feature)
+//!
+//! Next to the global logger, log frames can be sent to any number of [`Sink`]s, each with its
+//! own maximum level. The frames are encoded once and the sinks are called while the global
+//! logger is acquired, so they need no locking of their own (which is why this feature can't be
+//! combined with a `preemptible-logger`).
+//!
+//! ``` ignore
+//! use defmt::{LevelFilter, Sink};
+//!
+//! struct FlashRing;
+//!
+//! unsafe impl Sink for FlashRing {
+//!     unsafe fn write(bytes: &[u8]) {
+//!         // ..
+//!     }
+//! }
+//!
+//! struct Uart;
+//!
+//! unsafe impl Sink for Uart {
+//!     // ..
+//! #   unsafe fn write(bytes: &[u8]) {}
+//! }
+//!
+//! defmt::sinks! {
+//!     FlashRing => LevelFilter::Info,
+//!     // the maximum level is evaluated whenever a frame is written so it can be changed at
+//!     // runtime (but must not change while a frame is being written)
+//!     Uart => uart_max_level(),
+//! }
+//! ```
+
+/// A destination for log frames next to the global logger; registered with [`sinks!`]
+///
+/// [`sinks!`]: macro.sinks.html
+///
+/// # Safety
+///
+/// The methods are only called with the global logger acquired; see [`Logger`](crate::Logger).
+/// Like the `Logger` methods they should never be invoked from user code.
+pub unsafe trait Sink {
+    /// Writes `bytes`, a part of a log frame, to the destination. The write operation must not
+    /// fail.
+    ///
+    /// # Safety
+    /// Must only be called when the global logger is acquired in the current execution context.
+    unsafe fn write(bytes: &[u8]);
+
+    /// Called once the log frame has been completely written, e.g. to commit it to storage.
+    ///
+    /// # Safety
+    /// Must only be called when the global logger is acquired in the current execution context.
+    unsafe fn end_frame() {}
+}
+
+/// Writes `bytes` to the sinks that accept frames of `level` (see `LevelFilter`)
+pub(crate) fn write(level: u8, bytes: &[u8]) {
+    extern "Rust" {
+        fn _defmt_sinks_write(level: u8, bytes: &[u8]);
+    }
+    unsafe { _defmt_sinks_write(level, bytes) }
+}
+
+/// Notifies the sinks that accept frames of `level` that the frame is complete
+pub(crate) fn end_frame(level: u8) {
+    extern "Rust" {
+        fn _defmt_sinks_end_frame(level: u8);
+    }
+    unsafe { _defmt_sinks_end_frame(level) }
+}
+
+// used when the application doesn't use `sinks!`
+#[export_name = "__defmt_default_sinks_write"]
+fn default_write(_level: u8, _bytes: &[u8]) {}
+
+#[export_name = "__defmt_default_sinks_end_frame"]
+fn default_end_frame(_level: u8) {}
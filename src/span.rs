@@ -0,0 +1,81 @@
+//! Spans: scoped enter / exit log frames (see `span!`)
+
+use core::sync::atomic::AtomicU16;
+
+use crate::{atomic, export, InternalFormatter, Str};
+
+/// A guard that logs the exit frame of a span when dropped; created with [`span!`]
+///
+/// [`span!`]: macro.span.html
+#[must_use = "the span is exited as soon as the guard is dropped"]
+pub struct Span {
+    // `None` if the span is filtered out
+    inner: Option<Inner>,
+}
+
+struct Inner {
+    name: Str,
+    id: u16,
+    level: u8,
+}
+
+/// Frame kind; the argument that follows the frame header
+const ENTER: u8 = 0;
+const EXIT: u8 = 1;
+
+impl Span {
+    /// Implementation detail
+    ///
+    /// Logs the enter frame of the span `name`, at `level` (see `LevelFilter`)
+    #[doc(hidden)]
+    pub fn enter(name: Str, level: u8) -> Self {
+        export::acquire();
+        let id = next_id();
+        log(name, ENTER, id, level);
+        export::release();
+
+        Self {
+            inner: Some(Inner { name, id, level }),
+        }
+    }
+
+    /// Implementation detail
+    ///
+    /// A span that doesn't log anything
+    #[doc(hidden)]
+    pub fn disabled() -> Self {
+        Self { inner: None }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(inner) = &self.inner {
+            export::acquire();
+            log(inner.name, EXIT, inner.id, inner.level);
+            export::release();
+        }
+    }
+}
+
+/// Must be called with the logger acquired.
+fn log(name: Str, kind: u8, id: u16, level: u8) {
+    let mut fmt = InternalFormatter::new();
+    #[cfg(feature = "sinks")]
+    fmt.level(level);
+    #[cfg(not(feature = "sinks"))]
+    let _ = level;
+    fmt.header(&name);
+    fmt.u8(&kind);
+    fmt.u16(&id);
+    fmt.finalize();
+}
+
+/// Returns the ID of the next span; IDs wrap around after 65535
+///
+/// Must be called with the logger acquired.
+fn next_id() -> u16 {
+    static NEXT: AtomicU16 = AtomicU16::new(0);
+
+    atomic::next_u16(&NEXT)
+}
@@ -32,3 +32,105 @@ fn trailing_comma() {
     defmt::warn!("test warn {=?}", 0,);
     defmt::error!("test error {=?}", 0,);
 }
+
+#[test]
+fn fields() {
+    let peer = 7u8;
+    defmt::info!("connected", peer = peer, rssi = -60i8);
+    defmt::warn!("retrying in {=u32} ms", 100, attempt = 3u8,);
+    defmt::error!("failed", reason = defmt::Debug2Format(&"timeout"));
+}
+
+#[test]
+fn named_args() {
+    let len = 4u16;
+    defmt::info!("read {len=u16} bytes");
+    defmt::info!("{=u8} {addr=u32:#x}", 1, addr = 0x2000_0000);
+    // `name = value` arguments that are not used by the format string are fields
+    defmt::info!("read {len=u16} bytes", len = len + 1, retries = 2u8);
+}
+
+#[test]
+fn macro_format_strings() {
+    macro_rules! radio_info {
+        ($fmt:literal $(, $arg:expr)* $(,)?) => {
+            defmt::info!(concat!("[radio] ", $fmt) $(, $arg)*)
+        };
+    }
+
+    radio_info!("channel {=u8}", 11);
+    radio_info!("idle");
+    defmt::info!(stringify!(ready));
+    defmt::warn!(core::concat!("retry #", 3, " in {=u32} ms"), 100);
+}
+
+#[test]
+fn instrument() {
+    struct Device {
+        id: u8,
+    }
+
+    impl Device {
+        #[defmt::instrument(level = "debug", args(self, offset), ret)]
+        fn read(&mut self, offset: u8) -> Result<u8, ()> {
+            if offset > 3 {
+                return Err(());
+            }
+            Ok(self.id + offset)
+        }
+    }
+
+    #[defmt::instrument]
+    fn checked(x: Option<u8>) -> Option<u8> {
+        let x = x?;
+        Some(x + 1)
+    }
+
+    impl defmt::Format for Device {
+        fn format(&self, f: defmt::Formatter) {
+            defmt::write!(f, "Device {}", self.id)
+        }
+    }
+
+    struct Buffer {
+        id: u8,
+        slots: [u8; 4],
+    }
+
+    // methods that return a borrow of `self`
+    impl Buffer {
+        #[defmt::instrument]
+        fn id_mut(&mut self) -> &mut u8 {
+            &mut self.id
+        }
+
+        #[defmt::instrument(args(i), ret)]
+        fn slot(&mut self, i: usize) -> Option<&mut u8> {
+            self.slots.get_mut(i)
+        }
+    }
+
+    let mut buffer = Buffer {
+        id: 0,
+        slots: [0; 4],
+    };
+    *buffer.id_mut() = 1;
+    *buffer.slot(2).unwrap() = 3;
+    assert!(buffer.slot(4).is_none());
+    assert_eq!((buffer.id, buffer.slots), (1, [0, 0, 3, 0]));
+
+    let mut device = Device { id: 7 };
+    assert_eq!(device.read(1), Ok(8));
+    assert_eq!(device.read(4), Err(()));
+    assert_eq!(checked(None), None);
+    assert_eq!(checked(Some(1)), Some(2));
+}
+
+#[test]
+fn span() {
+    let _outer = defmt::span!("outer");
+    {
+        let _inner = defmt::span!("inner {braces}");
+        defmt::trace!("in the inner span");
+    }
+}
@@ -87,4 +87,14 @@ pub unsafe trait Logger {
     /// Must only be called when the global logger is acquired in the current execution context.
     /// (i.e. between acquire() and release())
     unsafe fn write(bytes: &[u8]);
+
+    /// Sends the log frames that have been buffered, if any, to the host.
+    ///
+    /// Loggers that hold on to log frames (e.g. a flight recorder) must implement this method; the
+    /// default implementation does nothing. It's called by [`flush`](crate::flush) and, therefore,
+    /// before `defmt::panic!` calls the panic handler.
+    ///
+    /// Like `acquire` this is a safe function and must be thread-safe and interrupt-safe; it is not
+    /// called with the global logger acquired in the current execution context.
+    fn flush() {}
 }
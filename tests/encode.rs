@@ -105,6 +105,46 @@ fn write() {
     );
 }
 
+#[test]
+fn write_named_args() {
+    let index = fetch_string_index();
+    let f = &mut InternalFormatter::new();
+    let g = Formatter { inner: f };
+
+    let captured = 3u8;
+    write!(
+        g,
+        "{=u16} {named=u8:x} {captured=u8} {named=u8}",
+        1,
+        named = 2
+    );
+    check!(
+        f.bytes(),
+        [
+            index, // "{=u16} {named=u8:x} {captured=u8} {named=u8}"
+            1u16,  // positional
+            2u8,   // named
+            3u8,   // captured
+        ]
+    );
+}
+
+#[test]
+fn write_concat() {
+    let index = fetch_string_index();
+    let f = &mut InternalFormatter::new();
+    let g = Formatter { inner: f };
+
+    write!(g, concat!("The answer is ", "{=u8}"), 42);
+    check!(
+        f.bytes(),
+        [
+            index, // "The answer is {=u8}"
+            42u8,  // u8 value
+        ]
+    );
+}
+
 #[test]
 fn bitfields_mixed() {
     let index = fetch_string_index();
@@ -783,6 +823,207 @@ fn derive_with_bounds() {
     );
 }
 
+#[test]
+fn derive_skip() {
+    struct NotFormat;
+
+    #[derive(Format)]
+    struct S {
+        a: u8,
+        #[defmt(skip)]
+        _b: NotFormat,
+        c: u16,
+    }
+
+    #[derive(Format)]
+    struct T(#[defmt(skip)] NotFormat, u8);
+
+    #[allow(dead_code)]
+    #[derive(Format)]
+    enum E {
+        A(#[defmt(skip)] NotFormat),
+        B {
+            #[defmt(skip)]
+            b: NotFormat,
+            c: u8,
+        },
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &S {
+            a: 1,
+            _b: NotFormat,
+            c: 2
+        },
+        [
+            index, // "S {{ a: {=u8}, c: {=u16} }}"
+            1u8,   // a
+            2u16,  // c
+        ],
+    );
+
+    let index = fetch_string_index();
+    check_format!(
+        &T(NotFormat, 3),
+        [
+            index, // "T({=u8})"
+            3u8,
+        ],
+    );
+
+    let index = fetch_string_index();
+    check_format!(
+        &E::B { b: NotFormat, c: 4 },
+        [
+            index, // "A|B {{ c: {=u8} }}"
+            1u8,   // discriminant
+            4u8,   // c
+        ],
+    );
+}
+
+#[test]
+fn derive_core_fmt_adapters() {
+    #[derive(Format)]
+    struct S {
+        #[defmt(Debug2Format)]
+        d: Option<u8>,
+        #[defmt(Display2Format)]
+        e: u8,
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &S { d: Some(1), e: 2 },
+        [
+            index, // "S {{ d: {=__internal_Debug}, e: {=__internal_Display} }}"
+            b'S', b'o', b'm', b'e', b'(', b'1', b')', 0xffu8, // d
+            b'2', 0xffu8, // e
+        ],
+    );
+}
+
+#[test]
+fn derive_hint() {
+    #[derive(Format)]
+    struct S {
+        #[defmt(hint = "#x")]
+        a: u8,
+        #[defmt(hint = "b")]
+        b: Option<u8>,
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &S { a: 1, b: Some(2) },
+        [
+            index,         // "S {{ a: {=u8:#x}, b: {=?:b} }}"
+            1u8,           // a
+            inc(index, 1), // "None|Some({=?})"
+            1u8,           // discriminant
+            inc(index, 2), // "{=u8}"
+            2u8,           // b
+        ],
+    );
+}
+
+#[test]
+fn derive_format_string() {
+    #[derive(Format)]
+    #[defmt(format = "{=u8}..{=u8}")]
+    struct Range {
+        start: u8,
+        end: u8,
+    }
+
+    #[derive(Format)]
+    #[defmt(format = "port {}")]
+    struct Port(#[defmt(skip)] (), #[defmt(Debug2Format)] u8);
+
+    let index = fetch_string_index();
+    check_format!(
+        &Range { start: 1, end: 5 },
+        [
+            index, // "{=u8}..{=u8}"
+            1u8,   // start
+            5u8,   // end
+        ],
+    );
+
+    let index = fetch_string_index();
+    check_format!(
+        &Port((), 7),
+        [
+            index,         // "port {}"
+            inc(index, 1), // "{=__internal_Debug}"
+            b'7',
+            0xffu8,
+        ],
+    );
+}
+
+#[test]
+fn derive_transparent() {
+    #[derive(Format)]
+    #[defmt(transparent)]
+    struct Id(u16);
+
+    #[derive(Format)]
+    #[defmt(transparent)]
+    struct Wrapper<T> {
+        inner: T,
+        #[defmt(skip)]
+        _len: usize,
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &Id(1),
+        [
+            index, // "{=u16}"
+            1u16,
+        ],
+    );
+
+    let index = fetch_string_index();
+    check_format!(
+        &Wrapper {
+            inner: Id(2),
+            _len: 0
+        },
+        [
+            index, // "{=u16}"
+            2u16,
+        ],
+    );
+}
+
+#[test]
+fn derive_bound() {
+    struct NotFormat;
+
+    #[derive(Format)]
+    #[defmt(bound = "")]
+    struct S<T> {
+        #[defmt(skip)]
+        _marker: core::marker::PhantomData<T>,
+        x: u8,
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &S::<NotFormat> {
+            _marker: core::marker::PhantomData,
+            x: 1
+        },
+        [
+            index, // "S {{ x: {=u8} }}"
+            1u8,
+        ],
+    );
+}
+
 #[test]
 fn format_bools() {
     #[derive(Format)]
@@ -878,3 +1119,148 @@ fn core_fmt_adapters() {
     let index = fetch_string_index();
     check_format!(&Display2Format(&123u8), [index, b'1', b'2', b'3', 0xffu8]);
 }
+
+#[test]
+fn encode_to_slice() {
+    let index = fetch_string_index();
+    let mut buf = [0; 8];
+    let len = defmt::encode!(&mut buf, "x={=u8} y={=?}", 42, 1u16).unwrap();
+    #[cfg(not(feature = "sequence-numbers"))]
+    check!(
+        &buf[..len],
+        [
+            index,         // "x={=u8} y={=?}"
+            42u8,          // x
+            inc(index, 1), // "{=u16}" / impl Format for u16
+            1u16,          // y
+        ]
+    );
+    // frames put into a buffer are not part of the log stream; their sequence number is 0
+    #[cfg(feature = "sequence-numbers")]
+    check!(
+        &buf[..len],
+        [
+            index,         // "x={=u8} y={=?}"
+            0u8,           // sequence number
+            42u8,          // x
+            inc(index, 1), // "{=u16}" / impl Format for u16
+            1u16,          // y
+        ]
+    );
+
+    let mut buf = [0; 4];
+    assert_eq!(
+        defmt::encode!(&mut buf, "{=u32}", 42),
+        Err(defmt::EncodeError::Overflow)
+    );
+}
+
+#[test]
+fn debug_struct() {
+    struct Header {
+        src: u8,
+        dst: Option<u8>,
+    }
+
+    impl Format for Header {
+        fn format(&self, f: Formatter) {
+            let mut header = f.debug_struct(defmt::intern!("Header"));
+            header.field(defmt::intern!("src"), &self.src);
+            if let Some(dst) = &self.dst {
+                header.field(defmt::intern!("dst"), dst);
+            }
+            header.finish();
+        }
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &Header { src: 1, dst: None },
+        [
+            inc(index, 1), // "{=istr} {{ {=__internal_FormatSequence} }}"
+            index,         // "Header"
+            1u8,           // element
+            inc(index, 3), // "{=istr}: {=?}"
+            inc(index, 2), // "src"
+            inc(index, 4), // "{=u8}"
+            1u8,           // src
+            0u8,           // end
+        ],
+    );
+}
+
+#[test]
+fn debug_tuple() {
+    struct Handle(u8);
+
+    impl Format for Handle {
+        fn format(&self, f: Formatter) {
+            f.debug_tuple(defmt::intern!("Handle")).field(&self.0);
+        }
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &Handle(1),
+        [
+            inc(index, 1), // "{=istr}({=__internal_FormatSequence})"
+            index,         // "Handle"
+            1u8,           // element
+            inc(index, 2), // "{=u8}"
+            1u8,           // .0
+            0u8,           // end
+        ],
+    );
+}
+
+#[test]
+fn debug_list() {
+    struct List;
+
+    impl Format for List {
+        fn format(&self, f: Formatter) {
+            f.debug_list().entry(&1u8).entry(&2u16);
+        }
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &List,
+        [
+            index,         // "[{=__internal_FormatSequence}]"
+            1u8,           // element
+            inc(index, 1), // "{=u8}"
+            1u8,           // [0]
+            1u8,           // element
+            inc(index, 2), // "{=u16}"
+            2u16,          // [1]
+            0u8,           // end
+        ],
+    );
+}
+
+#[test]
+fn debug_map() {
+    struct Map;
+
+    impl Format for Map {
+        fn format(&self, f: Formatter) {
+            f.debug_map().entries([(1u8, 2u8)].iter().copied());
+        }
+    }
+
+    let index = fetch_string_index();
+    check_format!(
+        &Map,
+        [
+            index,         // "{{{=__internal_FormatSequence}}}"
+            1u8,           // element
+            inc(index, 1), // "{=?}: {=?}"
+            inc(index, 2), // "{=u8}"
+            1u8,           // key
+            inc(index, 3), // "{=u8}"
+            2u8,           // value
+            0u8,           // end
+        ],
+    );
+}
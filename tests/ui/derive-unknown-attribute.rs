@@ -0,0 +1,7 @@
+#[derive(defmt::Format)]
+struct S {
+    #[defmt(Debug)]
+    x: u8,
+}
+
+fn main() {}
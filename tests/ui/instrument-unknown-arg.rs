@@ -0,0 +1,4 @@
+#[defmt::instrument(args(len))]
+fn foo(x: bool) {}
+
+fn main() {}
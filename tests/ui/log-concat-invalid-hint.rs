@@ -0,0 +1,4 @@
+fn main() {
+    // the error can't point into a format string built by `concat!`; its message names the part
+    defmt::info!(concat!("x={=u8", ":dunno}"), 42)
+}
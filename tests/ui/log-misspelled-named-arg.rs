@@ -0,0 +1,5 @@
+fn main() {
+    let peer = 1u8;
+    // a field named almost like a named parameter is most likely a misspelled named argument
+    defmt::info!("connected to {peer=u8}", peeer = 2u8)
+}
@@ -0,0 +1,11 @@
+const FORMAT: &str = "{=u8}";
+
+struct S;
+
+impl defmt::Format for S {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, FORMAT, 1)
+    }
+}
+
+fn main() {}
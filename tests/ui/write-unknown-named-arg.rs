@@ -0,0 +1,9 @@
+struct S;
+
+impl defmt::Format for S {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "S({=u8}, {len=u16})", 1)
+    }
+}
+
+fn main() {}
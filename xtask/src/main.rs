@@ -112,6 +112,18 @@ fn test_host(deny_warnings: bool) {
         "host",
     );
 
+    do_test(
+        || {
+            run_command(
+                "cargo",
+                &["check", "-p", "defmt", "--features", OPT_IN_FEATURES],
+                None,
+                &env,
+            )
+        },
+        "host",
+    );
+
     do_test(
         || {
             run_command(
@@ -137,6 +149,9 @@ fn test_host(deny_warnings: bool) {
     );
 }
 
+/// Features of the `defmt` crate that change what's sent over the wire
+const OPT_IN_FEATURES: &str = "build-id,crc,sequence-numbers,sinks";
+
 fn test_cross() {
     println!("🧪 cross");
     let targets = [
@@ -161,6 +176,25 @@ fn test_cross() {
             },
             "cross",
         );
+        do_test(
+            || {
+                run_command(
+                    "cargo",
+                    &[
+                        "check",
+                        "--target",
+                        target,
+                        "-p",
+                        "defmt",
+                        "--features",
+                        OPT_IN_FEATURES,
+                    ],
+                    None,
+                    &[],
+                )
+            },
+            "cross",
+        );
     }
 
     do_test(
@@ -230,6 +264,24 @@ fn test_cross() {
             )
         },
         "cross",
+    );
+
+    do_test(
+        || {
+            run_command(
+                "cargo",
+                &[
+                    "check",
+                    "--target",
+                    "thumbv6m-none-eabi",
+                    "--features",
+                    "per-context-buffers",
+                ],
+                Some("firmware/defmt-rtt"),
+                &[],
+            )
+        },
+        "cross",
     )
 }
 